@@ -0,0 +1,200 @@
+use super::format::Table;
+use super::search::{Scope, split_regexp};
+use super::word::word_regexp;
+use super::{ConcordanceArgs, ConcordanceSort, Format, VerseRef};
+use biblearchive::BARFile;
+use regex::{Regex, RegexBuilder};
+use std::error::Error;
+use std::io::{Read, Seek};
+
+/// An occurrence of the keyword with the text either side of it
+struct Line {
+    verse_ref: VerseRef,
+    left: String,
+    keyword: String,
+    right: String,
+}
+
+impl Line {
+    /// The words of the left context, nearest the keyword first
+    fn left_key(&self) -> (String, Vec<String>) {
+        (
+            self.keyword.to_lowercase(),
+            sort_words(&self.left).into_iter().rev().collect(),
+        )
+    }
+
+    fn right_key(&self) -> (String, Vec<String>) {
+        (self.keyword.to_lowercase(), sort_words(&self.right))
+    }
+}
+
+/// Words to sort the context by, ignoring case and punctuation
+fn sort_words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+fn last_chars(text: &str, n: usize) -> String {
+    let len = text.chars().count();
+    text.chars().skip(len.saturating_sub(n)).collect()
+}
+
+fn first_chars(text: &str, n: usize) -> String {
+    text.chars().take(n).collect()
+}
+
+pub fn concordance<T: Read + Seek>(
+    bar: BARFile<T>,
+    params: &ConcordanceArgs,
+    format: Format,
+) -> i32 {
+    match concordance_internal(bar, params, format) {
+        Err(error) => {
+            eprintln!("Error while building concordance for {}", params.pattern);
+            eprintln!("{}", error);
+            1
+        }
+        Ok(_) => 0,
+    }
+}
+
+fn concordance_internal<T: Read + Seek>(
+    bar: BARFile<T>,
+    params: &ConcordanceArgs,
+    format: Format,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut output: Vec<String> = Vec::new();
+    let scope = Scope::parse(&params.include)?;
+    let regex: Regex = match split_regexp(&params.pattern) {
+        Some((pattern, ignore_case)) => {
+            match RegexBuilder::new(pattern)
+                .case_insensitive(ignore_case)
+                .build()
+            {
+                Ok(regex) => regex,
+                Err(_) => return Err(format!("Invalid regexp: {}", params.pattern).into()),
+            }
+        }
+        None => match word_regexp(&params.pattern) {
+            Some(regex) => regex,
+            None => return Err(format!("Invalid word: {}", params.pattern).into()),
+        },
+    };
+
+    let mut lines: Vec<Line> = Vec::new();
+    for book in bar.books_in_order() {
+        let b = book.book_number();
+        if !scope.includes_book(b) {
+            continue;
+        }
+        for chapter in book.chapters().flatten() {
+            let c = chapter.chapter_number();
+            if !scope.includes_chapter(b, c) {
+                continue;
+            }
+            for (v, verse) in chapter.enumerated_verses() {
+                let verse_ref = VerseRef::new(b, c, v);
+                if !scope.includes_verse(&verse_ref) {
+                    continue;
+                }
+                for m in regex.find_iter(&verse).filter(|m| !m.is_empty()) {
+                    lines.push(Line {
+                        verse_ref,
+                        left: last_chars(&verse[..m.start()], params.width),
+                        keyword: m.as_str().to_string(),
+                        right: first_chars(&verse[m.end()..], params.width),
+                    });
+                }
+            }
+        }
+    }
+    // The sorts are stable so lines with the same key stay in order
+    match params.sort {
+        ConcordanceSort::Reference => (),
+        ConcordanceSort::Left => lines.sort_by_cached_key(Line::left_key),
+        ConcordanceSort::Right => lines.sort_by_cached_key(Line::right_key),
+    }
+
+    if !format.is_text() {
+        let mut table = Table::new(&["reference", "left", "keyword", "right"]);
+        for line in lines {
+            table.push(vec![
+                line.verse_ref.to_string().into(),
+                line.left.into(),
+                line.keyword.into(),
+                line.right.into(),
+            ]);
+        }
+        for line in table.render(format) {
+            oprintln!(output, "{}", line);
+        }
+        return Ok(output);
+    }
+    // Line the keywords up in a column
+    let references: Vec<String> = lines
+        .iter()
+        .map(|line| line.verse_ref.to_string())
+        .collect();
+    let reference_width = references.iter().map(|r| r.len()).max().unwrap_or(0);
+    for (reference, line) in references.iter().zip(lines.iter()) {
+        let text = format!(
+            "{:<reference_width$}  {:>width$}{}{}",
+            reference,
+            line.left,
+            line.keyword,
+            line.right,
+            reference_width = reference_width,
+            width = params.width
+        );
+        oprintln!(output, "{}", text.trim_end());
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    fn barfile() -> BARFile<File> {
+        BARFile::open("tests/data/KJV.ibar").unwrap()
+    }
+
+    #[test]
+    fn test_concordance_sorts() {
+        let mut params = ConcordanceArgs {
+            pattern: "/testimon(y|ies)/".to_string(),
+            include: vec!["Ps 119:1-40".to_string()],
+            sort: ConcordanceSort::Right,
+            width: 12,
+        };
+        let output = concordance_internal(barfile(), &params, Format::Text).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "Ps 119:22  ve kept thy testimonies.",
+                "Ps 119:24          Thy testimonies also are my",
+                "Ps 119:36  rt unto thy testimonies, and not to",
+                "Ps 119:2   at keep his testimonies, and that s",
+                "Ps 119:14   way of thy testimonies, as much as",
+                "Ps 119:31  ck unto thy testimonies: O Lord, pu",
+            ]
+        );
+        params.sort = ConcordanceSort::Left;
+        let output = concordance_internal(barfile(), &params, Format::Text).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "Ps 119:2   at keep his testimonies, and that s",
+                "Ps 119:24          Thy testimonies also are my",
+                "Ps 119:22  ve kept thy testimonies.",
+                "Ps 119:14   way of thy testimonies, as much as",
+                "Ps 119:31  ck unto thy testimonies: O Lord, pu",
+                "Ps 119:36  rt unto thy testimonies, and not to",
+            ]
+        );
+    }
+}
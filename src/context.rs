@@ -0,0 +1,133 @@
+use super::VerseRef;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// A verse to be output along with whether it is a hit or just context
+pub struct ContextLine {
+    pub verse_ref: VerseRef,
+    pub text: String,
+    pub is_hit: bool,
+    /// Whether there is a gap between this and the previous line output
+    pub is_new_group: bool,
+}
+
+impl fmt::Display for ContextLine {
+    /// Hits are marked with ">" to pick them out from the context
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let marker = if self.is_hit { ">" } else { " " };
+        write!(f, "{} {} {}", marker, self.verse_ref, self.text)
+    }
+}
+
+/// Picks out the hits from a stream of verses in canonical order along with
+/// the verses around them, in the same way as grep's context output.
+/// Overlapping windows are merged so no verse is output twice.
+pub struct Context {
+    before: usize,
+    after: usize,
+    buffer: VecDeque<(VerseRef, String)>,
+    remaining_after: usize,
+    has_output: bool,
+    has_gap: bool,
+}
+
+impl Context {
+    pub fn new(before: usize, after: usize) -> Context {
+        Context {
+            before,
+            after,
+            buffer: VecDeque::new(),
+            remaining_after: 0,
+            has_output: false,
+            has_gap: false,
+        }
+    }
+
+    fn line(&mut self, verse_ref: VerseRef, text: String, is_hit: bool) -> ContextLine {
+        let is_new_group = self.has_output && self.has_gap;
+        self.has_output = true;
+        self.has_gap = false;
+        ContextLine {
+            verse_ref,
+            text,
+            is_hit,
+            is_new_group,
+        }
+    }
+
+    /// Add the next verse of the stream, returning any verses that should
+    /// now be output
+    pub fn push(&mut self, verse_ref: VerseRef, text: String, is_hit: bool) -> Vec<ContextLine> {
+        let mut lines = Vec::new();
+        if is_hit {
+            while let Some((r, t)) = self.buffer.pop_front() {
+                lines.push(self.line(r, t, false));
+            }
+            lines.push(self.line(verse_ref, text, true));
+            self.remaining_after = self.after;
+        } else if self.remaining_after > 0 {
+            self.remaining_after -= 1;
+            lines.push(self.line(verse_ref, text, false));
+        } else {
+            self.buffer.push_back((verse_ref, text));
+            if self.buffer.len() > self.before {
+                self.buffer.pop_front();
+                self.has_gap = true;
+            }
+        }
+        lines
+    }
+
+    /// Break the stream, e.g. at the end of a book or where verses are not
+    /// being searched, so that context does not run across the break
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.remaining_after = 0;
+        self.has_gap = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(context: &mut Context, hits: &[bool]) -> Vec<String> {
+        let mut output = Vec::new();
+        for (v, is_hit) in hits.iter().enumerate() {
+            let verse_ref = VerseRef::new(19, 1, v as u8 + 1);
+            for line in context.push(verse_ref, String::new(), *is_hit) {
+                if line.is_new_group {
+                    output.push("--".to_string());
+                }
+                let marker = if line.is_hit { ">" } else { " " };
+                output.push(format!("{}{}", marker, line.verse_ref.verse));
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn test_context_windows() {
+        let hits = [
+            false, false, true, false, false, false, false, false, true, false, true, false,
+        ];
+        assert_eq!(
+            run(&mut Context::new(1, 1), &hits),
+            vec![" 2", ">3", " 4", "--", " 8", ">9", " 10", ">11", " 12"]
+        );
+        assert_eq!(
+            run(&mut Context::new(0, 2), &hits),
+            vec![">3", " 4", " 5", "--", ">9", " 10", ">11", " 12"]
+        );
+    }
+
+    #[test]
+    fn test_context_reset() {
+        let mut context = Context::new(2, 2);
+        let output = run(&mut context, &[false, true, false]);
+        assert_eq!(output, vec![" 1", ">2", " 3"]);
+        context.reset();
+        let output = run(&mut context, &[true]);
+        assert_eq!(output, vec!["--", ">1"]);
+    }
+}
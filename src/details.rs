@@ -1,3 +1,5 @@
+use super::Format;
+use super::format::Table;
 use biblearchive::BARFile;
 use humansize;
 use std::collections::HashMap;
@@ -6,14 +8,51 @@ use std::{
     time::Duration,
 };
 
-pub fn details<T: Read + Seek>(bar: BARFile<T>, compression_details: bool) -> Vec<String> {
+/// Print a line of text, or for the structured formats record the
+/// equivalent property and value instead
+macro_rules! property {
+    ($output:ident, $table:ident, $format:ident, $name:expr, $value:expr, $($arg:tt)*) => {
+        if $format.is_text() {
+            oprintln!($output, $($arg)*);
+        } else {
+            $table.push(vec![$name.into(), $value.into()]);
+        }
+    };
+}
+
+pub fn details<T: Read + Seek>(
+    bar: BARFile<T>,
+    compression_details: bool,
+    format: Format,
+) -> Vec<String> {
     let mut output: Vec<String> = Vec::new();
+    let mut table = Table::new(&["property", "value"]);
     let mut pending: Vec<String> = Vec::new();
-    oprintln!(output, "Version {}", bar.archive_version());
-    oprintln!(output, "{}", bar.bible_version());
+    property!(
+        output,
+        table,
+        format,
+        "archive_version",
+        bar.archive_version().to_string(),
+        "Version {}",
+        bar.archive_version()
+    );
+    property!(
+        output,
+        table,
+        format,
+        "bible_version",
+        bar.bible_version().to_string(),
+        "{}",
+        bar.bible_version()
+    );
     let file_size = bar.len();
-    oprintln!(
+    property!(
         output,
+        table,
+        format,
+        "size",
+        file_size,
         "Size: {}",
         humansize::format_size(file_size, humansize::BINARY)
     );
@@ -41,7 +80,14 @@ pub fn details<T: Read + Seek>(bar: BARFile<T>, compression_details: bool) -> Ve
                 chapters_present.push(i);
             }
         }
-        if chapters_present.len() == book.number_of_chapters() as usize {
+        if !format.is_text() {
+            let value = if chapters_present.len() == book.number_of_chapters() as usize {
+                "complete".to_string()
+            } else {
+                format!("chapters present: {:?}", chapters_present)
+            };
+            table.push(vec![book.book_name().to_string().into(), value.into()]);
+        } else if chapters_present.len() == book.number_of_chapters() as usize {
             if all_present {
                 pending.push(format!("{} ✓", book.book_name()))
             } else {
@@ -61,7 +107,9 @@ pub fn details<T: Read + Seek>(bar: BARFile<T>, compression_details: bool) -> Ve
             );
         }
     }
-    if all_present && pending.len() == 66 {
+    if !format.is_text() {
+        // Book details have already been recorded
+    } else if all_present && pending.len() == 66 {
         oprintln!(output, "All books present and complete");
     } else {
         for line in pending.iter() {
@@ -93,18 +141,34 @@ pub fn details<T: Read + Seek>(bar: BARFile<T>, compression_details: bool) -> Ve
                 }
             }
         }
-        oprintln!(
+        property!(
             output,
+            table,
+            format,
+            "uncompressed_size",
+            uncompressed_size,
             "Uncompressed size: {}",
             humansize::format_size(uncompressed_size, humansize::BINARY)
         );
-        oprintln!(
+        property!(
             output,
+            table,
+            format,
+            "compressed_size",
+            compressed_size,
             "Compressed size: {}",
             humansize::format_size(compressed_size, humansize::BINARY)
         );
         let compression = (uncompressed_size - file_size as u32) as f64 / uncompressed_size as f64;
-        oprintln!(output, "Compression: {:.0}%", compression * 100.0);
+        property!(
+            output,
+            table,
+            format,
+            "compression_percent",
+            (compression * 100.0).round(),
+            "Compression: {:.0}%",
+            compression * 100.0
+        );
 
         // Compression algorithms used
         let mut total = 0;
@@ -112,26 +176,42 @@ pub fn details<T: Read + Seek>(bar: BARFile<T>, compression_details: bool) -> Ve
             total += val;
         }
         for (key, val) in compression_algorithms.iter() {
-            oprintln!(
+            let percent = (val * 100) as f64 / total as f64;
+            property!(
                 output,
+                table,
+                format,
+                format!("used_compression_{}_percent", key),
+                percent.round(),
                 "Used compression {}: {:.0}%",
                 key,
-                ((val * 100) as f64 / total as f64)
+                percent
             );
         }
 
-        oprintln!(
+        property!(
             output,
+            table,
+            format,
+            "decompression_time_ms",
+            decompress_time.as_millis() as u64,
             "Decompression time: {} ms",
             decompress_time.as_millis()
         );
         let speed = uncompressed_size as f64 / (decompress_time.as_secs_f64() * 1000.0);
-        oprintln!(
+        property!(
             output,
+            table,
+            format,
+            "decompression_speed_bytes_per_ms",
+            speed as u64,
             "Decompression speed: {}/ms",
             humansize::format_size(speed as u64, humansize::BINARY)
         );
     }
 
+    for line in table.render(format) {
+        oprintln!(output, "{}", line);
+    }
     output
 }
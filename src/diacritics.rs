@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::sync::LazyLock;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+/// The blocks with letters made up of a base letter and diacritics: Latin,
+/// Greek, Cyrillic and the extended Latin and Greek
+const BLOCKS: [(u32, u32); 4] = [
+    (0x00c0, 0x024f),
+    (0x0370, 0x04ff),
+    (0x1e00, 0x1eff),
+    (0x1f00, 0x1fff),
+];
+
+/// The letters with diacritics for each base letter e.g. e has é, è, ê, ë...
+static ACCENTED: LazyLock<HashMap<char, String>> = LazyLock::new(|| {
+    let mut accented: HashMap<char, String> = HashMap::new();
+    for (first, last) in BLOCKS {
+        for c in (first..=last).filter_map(char::from_u32) {
+            let base: Vec<char> = fold(&c.to_string()).chars().collect();
+            if let [base] = base[..]
+                && base != c
+            {
+                accented.entry(base).or_default().push(c);
+            }
+        }
+    }
+    accented
+});
+
+/// The text without its diacritics e.g. José becomes Jose and ἀρχῇ becomes
+/// αρχη. Letters are decomposed first so that precomposed letters lose their
+/// diacritics too.
+pub fn fold(text: &str) -> String {
+    text.nfd().filter(|c| !is_combining_mark(*c)).collect()
+}
+
+/// Make a pattern match its letters with or without diacritics, whether the
+/// letters are precomposed or followed by combining marks. Escapes such as
+/// \p{L}, character classes and group flags and names are left as they are.
+pub fn fold_pattern(pattern: &str) -> String {
+    let mut folded = String::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                folded.push(c);
+                let escaped = chars.next();
+                folded.extend(escaped);
+                match escaped {
+                    // \p{Greek}, \x{e9}, \u{e9} and the like
+                    Some('p' | 'P' | 'x' | 'u' | 'U') if chars.peek() == Some(&'{') => {
+                        copy_through(&mut chars, &mut folded, '}');
+                    }
+                    // \pL
+                    Some('p' | 'P') => folded.extend(chars.next()),
+                    Some('x') => folded.extend(chars.by_ref().take(2)),
+                    Some('u') => folded.extend(chars.by_ref().take(4)),
+                    Some('U') => folded.extend(chars.by_ref().take(8)),
+                    _ => (),
+                }
+            }
+            '[' => {
+                folded.push(c);
+                copy_class(&mut chars, &mut folded);
+            }
+            '(' if chars.peek() == Some(&'?') => {
+                folded.push(c);
+                // Up to the end of the flags or the group's name
+                for c in chars.by_ref() {
+                    folded.push(c);
+                    if ":)>".contains(c) {
+                        break;
+                    }
+                }
+            }
+            c if c.is_alphabetic() => {
+                let base = fold(&c.to_string());
+                let letters = match base.chars().next().and_then(|b| ACCENTED.get(&b)) {
+                    Some(accented) => format!("[{}{}]", base, accented),
+                    None => base,
+                };
+                // Grouped, so any quantifier after the letter still applies
+                // to all of it
+                folded.push_str(&format!(r"(?:{}\p{{M}}*)", letters));
+            }
+            c => folded.push(c),
+        }
+    }
+    folded
+}
+
+fn copy_through(chars: &mut Peekable<Chars>, folded: &mut String, last: char) {
+    for c in chars.by_ref() {
+        folded.push(c);
+        if c == last {
+            break;
+        }
+    }
+}
+
+/// Copy the rest of a character class, including any classes nested in it,
+/// after its opening [
+fn copy_class(chars: &mut Peekable<Chars>, folded: &mut String) {
+    // A ] straight after the [ or [^ is part of the class
+    if chars.peek() == Some(&'^') {
+        folded.extend(chars.next());
+    }
+    if chars.peek() == Some(&']') {
+        folded.extend(chars.next());
+    }
+    while let Some(c) = chars.next() {
+        folded.push(c);
+        match c {
+            '\\' => {
+                let escaped = chars.next();
+                folded.extend(escaped);
+                if matches!(escaped, Some('p' | 'P' | 'x' | 'u' | 'U'))
+                    && chars.peek() == Some(&'{')
+                {
+                    copy_through(chars, folded, '}');
+                }
+            }
+            '[' => copy_class(chars, folded),
+            ']' => break,
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn test_fold() {
+        assert_eq!(fold("José"), "Jose");
+        assert_eq!(fold("Señor über"), "Senor uber");
+        assert_eq!(fold("ἐν ἀρχῇ ἦν ὁ λόγος"), "εν αρχη ην ο λογος");
+        assert_eq!(fold("בְּרֵאשִׁית"), "בראשית");
+
+        let regex = Regex::new(&fold_pattern(r"\b(?i:senor)e?\b")).unwrap();
+        for text in ["Señor", "SEÑOR", "senor", "senore", "sen\u{303}or"] {
+            assert!(regex.is_match(text), "{}", text);
+        }
+        assert!(!regex.is_match("senors"));
+        let regex = Regex::new(&fold_pattern(r"\bλογος\b")).unwrap();
+        assert!(regex.is_match("ὁ λόγος"));
+
+        // Escapes, classes and group names are left alone
+        assert_eq!(fold_pattern(r"\p{L}+\pL\x{e9}\x41"), r"\p{L}+\pL\x{e9}\x41");
+        assert_eq!(
+            fold_pattern(r"[aeiou][^]a-z[:alpha:]]"),
+            r"[aeiou][^]a-z[:alpha:]]"
+        );
+        let regex = Regex::new(&fold_pattern(r"(?<end>[aeiou]n)\b")).unwrap();
+        assert_eq!(&regex.captures("José can").unwrap()["end"], "an");
+        let regex = Regex::new(&fold_pattern(r"[aeiou]n")).unwrap();
+        assert!(regex.is_match("señor") && !regex.is_match("ñ"));
+    }
+}
@@ -0,0 +1,261 @@
+use super::format::{Table, VERSE_COLUMNS, verse_row};
+use super::search::Scope;
+use super::word::words;
+use super::{FindArgs, Format, VerseRef};
+use biblearchive::BARFile;
+use std::error::Error;
+use std::io::{Read, Seek};
+
+/// How alike two words must be, from 0 to 1, to count as the same word
+/// misremembered or misspelt
+const WORD_SIMILARITY: f64 = 0.75;
+
+/// How many times the number of verses wanted are compared letter by letter
+/// after being shortlisted by their words
+const SHORTLIST: usize = 10;
+
+/// The number of single character insertions, deletions and substitutions
+/// needed to turn one string into the other
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// The fewest edits needed to turn the text into any part of the verse, so
+/// a quotation of part of a verse is not penalised for what it leaves out
+fn substring_distance(text: &[char], verse: &[char]) -> usize {
+    // Starting the match anywhere in the verse is free
+    let mut previous = vec![0; verse.len() + 1];
+    let mut current = vec![0; verse.len() + 1];
+    for (i, ct) in text.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cv) in verse.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ct != cv);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    // As is ending it anywhere
+    previous.into_iter().min().unwrap_or_default()
+}
+
+/// How alike two words are, from 0 for nothing in common to 1 for the same
+fn word_similarity(a: &[char], b: &[char]) -> f64 {
+    if a == b {
+        return 1.0;
+    }
+    let longest = a.len().max(b.len());
+    // Too different in length to be close enough
+    if (a.len().abs_diff(b.len()) as f64) > longest as f64 * (1.0 - WORD_SIMILARITY) {
+        return 0.0;
+    }
+    let similarity = 1.0 - edit_distance(a, b) as f64 / longest as f64;
+    if similarity >= WORD_SIMILARITY {
+        similarity
+    } else {
+        0.0
+    }
+}
+
+/// A verse in the scope of the search, with its words ready to compare
+struct Verse {
+    verse_ref: VerseRef,
+    text: String,
+    words: Vec<Vec<char>>,
+}
+
+impl Verse {
+    /// How well each of the words of the text is matched in the verse
+    fn word_matches(&self, text_words: &[Vec<char>]) -> Vec<f64> {
+        text_words
+            .iter()
+            .map(|text_word| {
+                self.words
+                    .iter()
+                    .map(|word| word_similarity(text_word, word))
+                    .fold(0.0, f64::max)
+            })
+            .collect()
+    }
+
+    /// The words of the verse joined by single spaces, to compare letter by
+    /// letter ignoring case and punctuation
+    fn letters(&self) -> Vec<char> {
+        join_words(&self.words)
+    }
+}
+
+fn join_words(words: &[Vec<char>]) -> Vec<char> {
+    words.join(&' ')
+}
+
+pub fn find<T: Read + Seek>(bar: BARFile<T>, params: &FindArgs, format: Format) -> i32 {
+    match find_internal(bar, params, format) {
+        Err(error) => {
+            eprintln!("Error while finding verses like {}", params.text);
+            eprintln!("{}", error);
+            1
+        }
+        Ok(_) => 0,
+    }
+}
+
+fn find_internal<T: Read + Seek>(
+    bar: BARFile<T>,
+    params: &FindArgs,
+    format: Format,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut output: Vec<String> = Vec::new();
+    let text_words: Vec<Vec<char>> = words(&params.text)
+        .map(|word| word.chars().collect())
+        .collect();
+    if text_words.is_empty() {
+        return Err("No words to find".into());
+    }
+    let scope = Scope::parse(&params.include)?;
+
+    // Score every verse by the words it shares with the text
+    let mut verses: Vec<(Verse, Vec<f64>)> = Vec::new();
+    for book in bar.books_in_order() {
+        let b = book.book_number();
+        if !scope.includes_book(b) {
+            continue;
+        }
+        for chapter in book.chapters().flatten() {
+            let c = chapter.chapter_number();
+            if !scope.includes_chapter(b, c) {
+                continue;
+            }
+            for (v, text) in chapter.enumerated_verses() {
+                let verse_ref = VerseRef::new(b, c, v);
+                if !scope.includes_verse(&verse_ref) {
+                    continue;
+                }
+                let verse = Verse {
+                    verse_ref,
+                    words: words(&text).map(|word| word.chars().collect()).collect(),
+                    text: text.to_string(),
+                };
+                let matches = verse.word_matches(&text_words);
+                verses.push((verse, matches));
+            }
+        }
+    }
+
+    // Words found in fewer verses count for more, as with ranking searches,
+    // so "lamp" matters more than "the"
+    let documents = verses.len() as f64;
+    let weights: Vec<f64> = (0..text_words.len())
+        .map(|i| {
+            let frequency = verses.iter().filter(|(_, m)| m[i] > 0.0).count() as f64;
+            (1.0 + (documents - frequency + 0.5) / (frequency + 0.5)).ln()
+        })
+        .collect();
+    let total_weight: f64 = weights.iter().sum();
+    let mut shortlist: Vec<(f64, Verse)> = verses
+        .into_iter()
+        .map(|(verse, matches)| {
+            let overlap: f64 = matches.iter().zip(&weights).map(|(m, w)| m * w).sum();
+            (overlap / total_weight, verse)
+        })
+        .filter(|(overlap, _)| *overlap > 0.0)
+        .collect();
+    // Stable, so equal scores stay in order
+    shortlist.sort_by(|a, b| b.0.total_cmp(&a.0));
+    shortlist.truncate(params.top * SHORTLIST);
+
+    // Then by how closely the text matches part of the verse letter by letter
+    let letters = join_words(&text_words);
+    let mut scored: Vec<(f64, Verse)> = shortlist
+        .into_iter()
+        .map(|(overlap, verse)| {
+            let distance = substring_distance(&letters, &verse.letters());
+            let closeness = 1.0 - (distance as f64 / letters.len() as f64).min(1.0);
+            ((overlap + closeness) / 2.0, verse)
+        })
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored.truncate(params.top);
+
+    if !format.is_text() {
+        let mut columns = VERSE_COLUMNS.to_vec();
+        columns.push("score");
+        let mut table = Table::new(&columns);
+        for (score, verse) in scored {
+            let mut row = verse_row(&verse.verse_ref, &verse.text);
+            row.push(((score * 1000.0).round() / 1000.0).into());
+            table.push(row);
+        }
+        for line in table.render(format) {
+            oprintln!(output, "{}", line);
+        }
+        return Ok(output);
+    }
+    for (score, verse) in scored {
+        oprintln!(output, "{:.3} {} {}", score, verse.verse_ref, verse.text);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    fn barfile() -> BARFile<File> {
+        BARFile::open("tests/data/KJV.ibar").unwrap()
+    }
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    #[test]
+    fn test_distances() {
+        assert_eq!(edit_distance(&chars("kitten"), &chars("sitting")), 3);
+        assert_eq!(edit_distance(&chars(""), &chars("lamp")), 4);
+        assert_eq!(
+            substring_distance(
+                &chars("lamp unto my feet"),
+                &chars("a lamp unto my feet and")
+            ),
+            0
+        );
+        assert_eq!(
+            substring_distance(&chars("lamp to my feet"), &chars("a lamp unto my feet")),
+            2
+        );
+        assert_eq!(word_similarity(&chars("feet"), &chars("feet")), 1.0);
+        assert_eq!(word_similarity(&chars("shepherd"), &chars("shepard")), 0.75);
+        assert_eq!(word_similarity(&chars("lamp"), &chars("light")), 0.0);
+    }
+
+    #[test]
+    fn test_find() {
+        let mut params = FindArgs {
+            text: "the one about the lamp unto my feet".to_string(),
+            top: 1,
+            ..Default::default()
+        };
+        let output = find_internal(barfile(), &params, Format::Text).unwrap();
+        assert_eq!(output.len(), 1);
+        assert!(output[0].contains("Ps 119:105 "), "{}", output[0]);
+
+        // Misspelt and misremembered
+        params.text = "the lord is my shepard i shall not lack".to_string();
+        params.include = vec!["Ps".to_string()];
+        params.top = 3;
+        let output = find_internal(barfile(), &params, Format::Csv).unwrap();
+        assert_eq!(output.len(), 4);
+        assert!(output[1].starts_with("Ps 23:1,"), "{}", output[1]);
+    }
+}
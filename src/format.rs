@@ -0,0 +1,292 @@
+use super::VerseRef;
+use clap::ValueEnum;
+use std::fmt;
+
+/// Output format for the results of a command
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Format {
+    /// Human readable text
+    #[default]
+    Text,
+    /// A JSON array of records
+    Json,
+    /// Comma separated values with a header row
+    Csv,
+    /// Tab separated values with a header row
+    Tsv,
+    /// A Markdown table
+    Markdown,
+}
+
+impl Format {
+    pub fn is_text(&self) -> bool {
+        *self == Format::Text
+    }
+}
+
+/// A single field of a record
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Text(String),
+    Integer(u64),
+    Float(f64),
+    Bool(bool),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Text(s) => write!(f, "{}", s),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Float(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Text(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Text(value)
+    }
+}
+
+impl From<u8> for Value {
+    fn from(value: u8) -> Self {
+        Value::Integer(value as u64)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(value: u32) -> Self {
+        Value::Integer(value as u64)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        Value::Integer(value as u64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+/// Records with named columns, collected so they can be written out in
+/// one of the structured formats
+pub struct Table {
+    columns: Vec<String>,
+    rows: Vec<Vec<Value>>,
+}
+
+impl Table {
+    pub fn new(columns: &[&str]) -> Table {
+        Table {
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<Value>) {
+        self.rows.push(row);
+    }
+
+    /// Add the rows of another table with the same columns
+    pub fn append(&mut self, other: Table) {
+        self.rows.extend(other.rows);
+    }
+
+    /// Render the records as lines of output. Nothing is rendered for the
+    /// text format as commands write their own text output.
+    pub fn render(&self, format: Format) -> Vec<String> {
+        match format {
+            Format::Text => Vec::new(),
+            Format::Json => self.render_json(),
+            Format::Csv => self.render_separated(",", csv_field),
+            Format::Tsv => self.render_separated("\t", tsv_field),
+            Format::Markdown => self.render_markdown(),
+        }
+    }
+
+    fn render_json(&self) -> Vec<String> {
+        if self.rows.is_empty() {
+            return vec!["[]".to_string()];
+        }
+        let mut lines = vec!["[".to_string()];
+        for (i, row) in self.rows.iter().enumerate() {
+            let fields: Vec<String> = self
+                .columns
+                .iter()
+                .zip(row.iter())
+                .map(|(column, value)| format!("{}: {}", json_string(column), json_value(value)))
+                .collect();
+            let separator = if i + 1 < self.rows.len() { "," } else { "" };
+            lines.push(format!("  {{{}}}{}", fields.join(", "), separator));
+        }
+        lines.push("]".to_string());
+        lines
+    }
+
+    fn render_separated(&self, separator: &str, field: fn(&str) -> String) -> Vec<String> {
+        let mut lines = Vec::new();
+        let header: Vec<String> = self.columns.iter().map(|c| field(c)).collect();
+        lines.push(header.join(separator));
+        for row in self.rows.iter() {
+            let fields: Vec<String> = row.iter().map(|v| field(&v.to_string())).collect();
+            lines.push(fields.join(separator));
+        }
+        lines
+    }
+
+    fn render_markdown(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        let header: Vec<String> = self.columns.iter().map(|c| markdown_cell(c)).collect();
+        lines.push(format!("| {} |", header.join(" | ")));
+        let rule: Vec<&str> = self.columns.iter().map(|_| "---").collect();
+        lines.push(format!("| {} |", rule.join(" | ")));
+        for row in self.rows.iter() {
+            let cells: Vec<String> = row.iter().map(|v| markdown_cell(&v.to_string())).collect();
+            lines.push(format!("| {} |", cells.join(" | ")));
+        }
+        lines
+    }
+}
+
+/// Columns for records of a single verse
+pub const VERSE_COLUMNS: [&str; 5] = ["reference", "book", "chapter", "verse", "text"];
+
+/// A record of a single verse, to go with VERSE_COLUMNS
+pub fn verse_row(verse_ref: &VerseRef, text: &str) -> Vec<Value> {
+    vec![
+        verse_ref.to_string().into(),
+        verse_ref.book_abbrev().into(),
+        verse_ref.chapter.into(),
+        verse_ref.verse.into(),
+        text.into(),
+    ]
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn json_value(value: &Value) -> String {
+    match value {
+        Value::Text(s) => json_string(s),
+        Value::Integer(n) => n.to_string(),
+        Value::Float(n) if n.is_finite() => n.to_string(),
+        Value::Float(_) => "null".to_string(),
+        Value::Bool(b) => b.to_string(),
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn tsv_field(s: &str) -> String {
+    s.replace(['\t', '\n', '\r'], " ")
+}
+
+fn markdown_cell(s: &str) -> String {
+    s.replace('|', "\\|").replace(['\n', '\r'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Table {
+        let mut table = Table::new(&["reference", "text"]);
+        table.push(vec![
+            "Ps 23:1".into(),
+            "The Lord is my shepherd; I shall not want.".into(),
+        ]);
+        table.push(vec!["Jn 11:35".into(), "Jesus wept, \"so\" | he".into()]);
+        table
+    }
+
+    #[test]
+    fn test_render_json() {
+        assert_eq!(
+            table().render(Format::Json),
+            vec![
+                "[",
+                r#"  {"reference": "Ps 23:1", "text": "The Lord is my shepherd; I shall not want."},"#,
+                r#"  {"reference": "Jn 11:35", "text": "Jesus wept, \"so\" | he"}"#,
+                "]",
+            ]
+        );
+        assert_eq!(Table::new(&["a"]).render(Format::Json), vec!["[]"]);
+        let mut numbers = Table::new(&["count", "ratio"]);
+        numbers.push(vec![3u32.into(), 0.5.into()]);
+        assert_eq!(
+            numbers.render(Format::Json)[1],
+            r#"  {"count": 3, "ratio": 0.5}"#
+        );
+    }
+
+    #[test]
+    fn test_render_csv_tsv_markdown() {
+        assert_eq!(
+            table().render(Format::Csv),
+            vec![
+                "reference,text",
+                "Ps 23:1,The Lord is my shepherd; I shall not want.",
+                r#"Jn 11:35,"Jesus wept, ""so"" | he""#,
+            ]
+        );
+        assert_eq!(
+            table().render(Format::Tsv)[2],
+            "Jn 11:35\tJesus wept, \"so\" | he"
+        );
+        assert_eq!(
+            table().render(Format::Markdown),
+            vec![
+                "| reference | text |",
+                "| --- | --- |",
+                "| Ps 23:1 | The Lord is my shepherd; I shall not want. |",
+                r#"| Jn 11:35 | Jesus wept, "so" \| he |"#,
+            ]
+        );
+        assert!(table().render(Format::Text).is_empty());
+    }
+}
@@ -0,0 +1,119 @@
+use clap::ValueEnum;
+use regex::Regex;
+use std::io::IsTerminal;
+
+/// When to colour the matches in the output
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Color {
+    /// Only when writing to a terminal and NO_COLOR is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl Color {
+    pub fn enabled(&self) -> bool {
+        match self {
+            Color::Auto => {
+                std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+            }
+            Color::Always => true,
+            Color::Never => false,
+        }
+    }
+}
+
+/// How to mark the matches in the text
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Markers {
+    /// Bold red using ANSI escape codes
+    Ansi,
+    /// [Square brackets] for where colour is not available
+    Brackets,
+}
+
+impl Markers {
+    fn start(&self) -> &'static str {
+        match self {
+            Markers::Ansi => "\x1b[1;31m",
+            Markers::Brackets => "[",
+        }
+    }
+
+    fn end(&self) -> &'static str {
+        match self {
+            Markers::Ansi => "\x1b[0m",
+            Markers::Brackets => "]",
+        }
+    }
+}
+
+/// Marks everything in a verse matched by any of the regexes
+pub struct Highlighter {
+    regexes: Vec<Regex>,
+    markers: Option<Markers>,
+}
+
+impl Highlighter {
+    pub fn new(regexes: Vec<Regex>, markers: Option<Markers>) -> Highlighter {
+        Highlighter { regexes, markers }
+    }
+
+    pub fn highlight(&self, text: &str) -> String {
+        let markers = match self.markers {
+            Some(markers) => markers,
+            None => return text.to_string(),
+        };
+        let mut spans: Vec<(usize, usize)> = self
+            .regexes
+            .iter()
+            .flat_map(|re| re.find_iter(text).map(|m| (m.start(), m.end())))
+            .filter(|(start, end)| start < end)
+            .collect();
+        spans.sort();
+        // Overlapping matches are marked as one
+        let mut merged: Vec<(usize, usize)> = Vec::new();
+        for (start, end) in spans {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        let mut highlighted = String::with_capacity(text.len());
+        let mut position = 0;
+        for (start, end) in merged {
+            highlighted.push_str(&text[position..start]);
+            highlighted.push_str(markers.start());
+            highlighted.push_str(&text[start..end]);
+            highlighted.push_str(markers.end());
+            position = end;
+        }
+        highlighted.push_str(&text[position..]);
+        highlighted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight() {
+        let regexes = vec![
+            Regex::new(r"\bshepherd\b").unwrap(),
+            Regex::new("my shep").unwrap(),
+            Regex::new("want").unwrap(),
+        ];
+        let text = "The Lord is my shepherd; I shall not want.";
+        assert_eq!(
+            Highlighter::new(regexes.clone(), Some(Markers::Brackets)).highlight(text),
+            "The Lord is [my shepherd]; I shall not [want]."
+        );
+        assert_eq!(
+            Highlighter::new(regexes.clone(), Some(Markers::Ansi)).highlight(text),
+            "The Lord is \x1b[1;31mmy shepherd\x1b[0m; I shall not \x1b[1;31mwant\x1b[0m."
+        );
+        assert_eq!(Highlighter::new(regexes, None).highlight(text), text);
+    }
+}
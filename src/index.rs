@@ -0,0 +1,317 @@
+use super::diacritics::fold;
+use super::format::Table;
+use super::stem::stem_regexp;
+use super::word::{is_wildcard, is_word_char, word_regexp, words};
+use super::{Format, VerseRef};
+use biblearchive::BARFile;
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const HEADER: &str = "BAR index 1";
+
+/// Verses that may match, or None if any verse may match
+pub type Candidates = Option<HashSet<VerseRef>>;
+
+/// Inverted index of the verses each word is found in, kept in a sidecar
+/// file next to the BARFile so searches only need to look at the chapters
+/// that might match. Words are lower case, so lookups give a superset of
+/// the verses that match and the verses still have to be checked.
+pub struct Index {
+    words: BTreeMap<String, Vec<VerseRef>>,
+}
+
+/// The size and modification time of the BARFile, to tell when the index is
+/// out of date
+fn fingerprint(archive: &Path) -> Result<String, Box<dyn Error>> {
+    let metadata = fs::metadata(archive)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?;
+    Ok(format!(
+        "archive {} {}.{:09}",
+        metadata.len(),
+        modified.as_secs(),
+        modified.subsec_nanos()
+    ))
+}
+
+impl Index {
+    /// The path of the index for a BARFile e.g. KJV.ibar.idx
+    pub fn path(archive: &Path) -> PathBuf {
+        let mut path = archive.as_os_str().to_owned();
+        path.push(".idx");
+        PathBuf::from(path)
+    }
+
+    pub fn build<T: Read + Seek>(bar: &BARFile<T>) -> Index {
+        let mut words_index: BTreeMap<String, Vec<VerseRef>> = BTreeMap::new();
+        for book in bar.books_in_order() {
+            let b = book.book_number();
+            for chapter in book.chapters().flatten() {
+                let c = chapter.chapter_number();
+                for (v, text) in chapter.enumerated_verses() {
+                    let verse_ref = VerseRef::new(b, c, v);
+                    for word in words(&text) {
+                        let verses = words_index.entry(word).or_default();
+                        if verses.last() != Some(&verse_ref) {
+                            verses.push(verse_ref);
+                        }
+                    }
+                }
+            }
+        }
+        Index { words: words_index }
+    }
+
+    /// Write the index for the BARFile at the given path
+    pub fn write(&self, archive: &Path) -> Result<PathBuf, Box<dyn Error>> {
+        let path = Index::path(archive);
+        let mut file = BufWriter::new(File::create(&path)?);
+        writeln!(file, "{}", HEADER)?;
+        writeln!(file, "{}", fingerprint(archive)?)?;
+        for (word, verses) in self.words.iter() {
+            write!(file, "{}", word)?;
+            for verse_ref in verses {
+                write!(
+                    file,
+                    " {}:{}:{}",
+                    verse_ref.book, verse_ref.chapter, verse_ref.verse
+                )?;
+            }
+            writeln!(file)?;
+        }
+        file.flush()?;
+        Ok(path)
+    }
+
+    /// Load the index for the BARFile at the given path. Returns None if
+    /// there is no index, or an error if it cannot be used.
+    pub fn load(archive: &Path) -> Result<Option<Index>, Box<dyn Error>> {
+        let path = Index::path(archive);
+        if !fs::exists(&path).unwrap_or(false) {
+            return Ok(None);
+        }
+        let mut lines = BufReader::new(File::open(&path)?).lines();
+        let bad_index = || format!("Index {} is not valid.", path.to_string_lossy());
+        if lines.next().transpose()?.as_deref() != Some(HEADER) {
+            return Err(bad_index().into());
+        }
+        if lines.next().transpose()? != Some(fingerprint(archive)?) {
+            return Err(format!(
+                "Index {} is out of date. Use bar index build to update it.",
+                path.to_string_lossy()
+            )
+            .into());
+        }
+        let mut words_index = BTreeMap::new();
+        for line in lines {
+            let line = line?;
+            let mut fields = line.split(' ');
+            let word = fields.next().ok_or_else(bad_index)?;
+            let mut verses = Vec::new();
+            for field in fields {
+                let numbers: Vec<u8> = field.split(':').filter_map(|n| n.parse().ok()).collect();
+                if numbers.len() != 3 {
+                    return Err(bad_index().into());
+                }
+                verses.push(VerseRef::new(numbers[0], numbers[1], numbers[2]));
+            }
+            words_index.insert(word.to_string(), verses);
+        }
+        Ok(Some(Index { words: words_index }))
+    }
+
+    fn verses_where(&self, f: impl Fn(&str) -> bool) -> HashSet<VerseRef> {
+        self.words
+            .iter()
+            .filter(|(word, _)| f(word))
+            .flat_map(|(_, verses)| verses.iter().copied())
+            .collect()
+    }
+
+    /// Verses that may contain the whole word, or words in sequence
+    pub fn word(&self, word: &str) -> Candidates {
+        if is_wildcard(word) {
+            // The words in the index are lower case
+            if word.split_whitespace().count() != 1 {
+                return None;
+            }
+            return Some(self.matching(&word_regexp(&word.to_lowercase())?, false));
+        }
+        let word = word.replace(|c: char| !is_word_char(c) && c != ' ', "");
+        words(&word)
+            .map(|w| match self.words.get(&w) {
+                Some(verses) => verses.iter().copied().collect(),
+                None => HashSet::new(),
+            })
+            .map(Some)
+            .reduce(both)
+            .flatten()
+    }
+
+    /// Verses that may contain any of the inflections of the word, or None
+    /// if there are several words
+    pub fn stem(&self, word: &str) -> Candidates {
+        if word.split_whitespace().count() != 1 {
+            return None;
+        }
+        // The words in the index are lower case
+        let regex = stem_regexp(&word.to_lowercase())?;
+        Some(self.matching(&regex, false))
+    }
+
+    /// Verses with a word that the whole of the regex matches, optionally
+    /// once the word's diacritics are removed
+    pub fn matching(&self, regex: &Regex, fold_words: bool) -> HashSet<VerseRef> {
+        self.verses_where(|w| {
+            let w = match fold_words {
+                true => Cow::Owned(fold(w)),
+                false => Cow::Borrowed(w),
+            };
+            regex
+                .find(&w)
+                .is_some_and(|m| m.start() == 0 && m.end() == w.len())
+        })
+    }
+
+    /// Verses that may contain the phrase. The phrase can start or end part
+    /// way through a word, so the words at either end are matched against
+    /// the ends of the words in the index.
+    pub fn phrase(&self, phrase: &str) -> Candidates {
+        let pieces: Vec<&str> = phrase.split(|c: char| !is_word_char(c)).collect();
+        let last = pieces.len() - 1;
+        pieces
+            .iter()
+            .enumerate()
+            .filter(|(_, piece)| !piece.is_empty())
+            .map(|(i, piece)| {
+                let piece = piece.to_lowercase();
+                Some(match (i == 0, i == last) {
+                    (true, true) => self.verses_where(|w| w.contains(&piece)),
+                    (true, false) => self.verses_where(|w| w.ends_with(&piece)),
+                    (false, true) => self.verses_where(|w| w.starts_with(&piece)),
+                    (false, false) => self.verses_where(|w| w == piece),
+                })
+            })
+            .reduce(both)
+            .flatten()
+    }
+
+    fn verse_count(&self) -> usize {
+        self.words
+            .values()
+            .flatten()
+            .collect::<HashSet<&VerseRef>>()
+            .len()
+    }
+}
+
+/// Verses that may match both
+pub fn both(a: Candidates, b: Candidates) -> Candidates {
+    match (a, b) {
+        (None, b) => b,
+        (a, None) => a,
+        (Some(a), Some(b)) => Some(a.intersection(&b).copied().collect()),
+    }
+}
+
+/// Verses that may match either
+pub fn either(a: Candidates, b: Candidates) -> Candidates {
+    match (a, b) {
+        (Some(mut a), Some(b)) => {
+            a.extend(b);
+            Some(a)
+        }
+        _ => None,
+    }
+}
+
+pub fn build_index<T: Read + Seek>(bar: BARFile<T>, archive: &Path, format: Format) -> i32 {
+    let index = Index::build(&bar);
+    match index.write(archive) {
+        Err(error) => {
+            eprintln!(
+                "Error while writing index for {}",
+                archive.to_string_lossy()
+            );
+            eprintln!("{}", error);
+            1
+        }
+        Ok(path) => {
+            let path = path.to_string_lossy().to_string();
+            let mut output: Vec<String> = Vec::new();
+            if format.is_text() {
+                oprintln!(
+                    output,
+                    "Index of {} words in {} verses written to {}",
+                    index.words.len(),
+                    index.verse_count(),
+                    path
+                );
+            } else {
+                let mut table = Table::new(&["path", "words", "verses"]);
+                table.push(vec![
+                    path.into(),
+                    index.words.len().into(),
+                    index.verse_count().into(),
+                ]);
+                for line in table.render(format) {
+                    oprintln!(output, "{}", line);
+                }
+            }
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> Index {
+        Index::build(&BARFile::open("tests/data/KJV.ibar").unwrap())
+    }
+
+    #[test]
+    fn test_index_lookup() {
+        let index = index();
+        let shepherd = index.word("Shepherd").unwrap();
+        assert!(shepherd.contains(&VerseRef::new(19, 23, 1)));
+        assert!(shepherd.contains(&VerseRef::new(19, 80, 1)));
+        assert!(!shepherd.contains(&VerseRef::new(19, 23, 2)));
+        assert!(index.word("shep").unwrap().is_empty());
+        // Phrases can start and end part way through words
+        let phrase = index.phrase("ord is my shep").unwrap();
+        assert!(phrase.contains(&VerseRef::new(19, 23, 1)));
+        assert!(
+            index
+                .phrase("shep")
+                .unwrap()
+                .contains(&VerseRef::new(19, 23, 1))
+        );
+        assert!(index.phrase(", ").is_none());
+    }
+
+    #[test]
+    fn test_index_write_and_load() {
+        let dir = std::env::temp_dir().join(format!("bar-index-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("KJV.ibar");
+        fs::copy("tests/data/KJV.ibar", &archive).unwrap();
+        assert!(Index::load(&archive).unwrap().is_none());
+        let index = index();
+        index.write(&archive).unwrap();
+        let loaded = Index::load(&archive).unwrap().unwrap();
+        assert_eq!(loaded.words, index.words);
+        // Changing the archive makes the index out of date
+        let mut file = fs::OpenOptions::new().append(true).open(&archive).unwrap();
+        file.write_all(b" ").unwrap();
+        drop(file);
+        assert!(Index::load(&archive).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,4 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -12,10 +12,14 @@ pub struct Args {
     #[arg(short, long, env = "BAR_DATADIR")]
     pub datadir: Option<PathBuf>,
 
-    /// Specify which version to use from the BARFiles in the datadir
+    /// Specify which version to use from the BARFiles in the datadir. Searches can use a comma separated list of versions
     #[arg(short, long, env = "BAR_DEFAULT_VERSION")]
     pub ver: Option<String>,
 
+    /// The format to write the results in
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+
     #[command(subcommand)]
     pub command: Option<Command>,
 }
@@ -30,30 +34,231 @@ pub enum Command {
     },
     /// Fetch one or more verses
     Verse {
-        /// Reference to the verses to fetch e.g. "Ge 1:1"
+        /// Reference to the verses to fetch e.g. "Ge 1:1", "Jn 3:16,18", "1 John 1:5-2:2", "Ps 23", "Jude"
         verses: Vec<String>,
+        /// Number of verses to show before and after each verse
+        #[arg(short = 'C', long, default_value_t = 0, conflicts_with = "compare")]
+        context: usize,
+        /// Show the verses in each of these versions from the datadir e.g. KJV,ASV,WEB
+        #[arg(long, value_delimiter = ',')]
+        compare: Vec<String>,
+        /// How to lay out the versions being compared
+        #[arg(long, value_enum, default_value_t = Layout::Interleaved)]
+        layout: Layout,
+        /// The total width to use for the columns layout
+        #[arg(long, default_value_t = 120)]
+        width: usize,
     },
     /// Perform a search for matching verses
     Search(SearchArgs),
+    /// Find the verses most like a misremembered or paraphrased quotation
+    Find(FindArgs),
+    /// List every occurrence of a word with the text either side of it
+    Concordance(ConcordanceArgs),
+    /// Count the words used and how often each occurs
+    Stats(StatsArgs),
+    /// Manage the index used to speed up searches
+    Index {
+        #[command(subcommand)]
+        command: IndexCommand,
+    },
 }
 
-#[derive(Parser)]
+#[derive(Subcommand)]
+pub enum IndexCommand {
+    /// Build the index for the BARFile, replacing any existing index
+    Build,
+}
+
+/// Layout to use when comparing versions
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Layout {
+    /// Each version of a verse on its own line
+    Interleaved,
+    /// The versions side by side in columns
+    Columns,
+}
+
+#[derive(Parser, Default)]
 pub struct SearchArgs {
     /// The phrase or pattern to match. eg. "edge of the sword", /prais(es?|ing|ed)/
     #[arg(short, long, num_args=1..)]
     matching: Vec<String>,
-    /// The word(s) that must be present
+    /// The word(s) that must be present. * and ? match any letters or any one letter e.g. bless*, wom?n
     #[arg(short, long, num_args=1..)]
     word: Vec<String>,
-    /// The books or chapters to include. eg. NT, OT, Ge, 1Sa..2Ch, "Ps 119"
+    /// A boolean query to match using AND, OR, NOT, NEAR/n and brackets. eg. "(grace OR mercy) AND NOT law", "\"my shepherd\" OR /sheep/i", "faith NEAR/5 works"
+    #[arg(short, long)]
+    query: Option<String>,
+    /// Measure NEAR distances in the query across adjacent verses of the same chapter
+    #[arg(long)]
+    across_verses: bool,
+    /// Match every inflection of the words and query words e.g. praise also matches praised, praiseth and praising
+    #[arg(long)]
+    stem: bool,
+    /// Also match other spellings of the words and phrases e.g. show also matches shew and has matches hath
+    #[arg(long)]
+    spelling: bool,
+    /// File of extra spellings to match, with a group of words on each line starting with the modern one. Implies --spelling
+    #[arg(long, env = "BAR_SPELLING_FILE")]
+    spelling_file: Option<PathBuf>,
+    /// Match words, and the letters of regexps, whether or not they have accents or other diacritics e.g. senor also matches señor
+    #[arg(long)]
+    ignore_diacritics: bool,
+    /// Match phrases ignoring case, punctuation and spacing e.g. "in the beginning God" also matches "In the beginning, God"
+    #[arg(long)]
+    normalize_phrases: bool,
+    /// The books, chapters or verses to include. eg. NT, OT, Ge, 1Sa..2Ch, "Ps 119", "Ps 119:1-8"
     #[arg(short, long, num_args=1..)]
     include: Vec<String>,
-    /// Count the verses that match in each chapter rather than displaying them all
+    /// Count the verses that match in each chapter rather than displaying them all. When only words are matched each word is counted too, in columns if there are several, along with the words each wildcard matched
     #[arg(short, long)]
     count: bool,
-    /// The threshold to use when reporting the chapter count
+    /// What to count the matches in
+    #[arg(short, long, value_enum, default_value_t = GroupBy::Chapter, requires = "count")]
+    group_by: GroupBy,
+    /// The threshold to use when reporting the counts, met by either the verse or word count
     #[arg(short, long)]
     threshold: Option<u32>,
+    /// Only report counts with at least this many matching verses
+    #[arg(long, requires = "count")]
+    min_verses: Option<u32>,
+    /// Only report counts with at least this many matching words, adding up the counts for each --word
+    #[arg(long, requires = "count")]
+    min_words: Option<u32>,
+    /// How to order the counts
+    #[arg(short, long, value_enum, default_value_t = CountSort::Reference, requires = "count")]
+    sort: CountSort,
+    /// When to colour the matches in the text output
+    #[arg(long, value_enum, default_value_t = Color::Auto)]
+    color: Color,
+    /// Mark the matches with [brackets] in the text and Markdown output instead
+    #[arg(long)]
+    brackets: bool,
+    /// List the matches that are most relevant to the words searched for, scored with BM25
+    #[arg(long, conflicts_with_all = ["count", "context", "before_context", "after_context"])]
+    rank: bool,
+    /// Whether to rank verses or whole chapters
+    #[arg(long, value_enum, default_value_t = RankBy::Verse, requires = "rank")]
+    rank_by: RankBy,
+    /// Number of the most relevant matches to list
+    #[arg(short = 'n', long, default_value_t = 10, requires = "rank")]
+    top: usize,
+    /// List each different text the /regexp/ matches and how often, instead of the verses
+    #[arg(long, conflicts_with_all = ["count", "rank", "context", "before_context", "after_context"])]
+    tally: bool,
+    /// Tally the text of this numbered or named capture group rather than the whole match
+    #[arg(long, requires = "tally")]
+    capture: Option<String>,
+    /// Search every version in the datadir
+    #[arg(long)]
+    pub all_versions: bool,
+    /// Number of threads to search with. Defaults to the number of CPUs
+    #[arg(short = 'j', long)]
+    threads: Option<usize>,
+    /// Number of verses to show after each match
+    #[arg(short = 'A', long, default_value_t = 0)]
+    after_context: usize,
+    /// Number of verses to show before each match
+    #[arg(short = 'B', long, default_value_t = 0)]
+    before_context: usize,
+    /// Number of verses to show before and after each match
+    #[arg(short = 'C', long, default_value_t = 0)]
+    context: usize,
+}
+
+/// What to count the matches of a search in
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum GroupBy {
+    Verse,
+    #[default]
+    Chapter,
+    Book,
+    /// Old and New Testaments
+    Testament,
+    /// Only the total
+    None,
+}
+
+/// What to rank the matches of a search by relevance
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum RankBy {
+    #[default]
+    Verse,
+    Chapter,
+}
+
+/// Order for the counts of a search
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum CountSort {
+    /// In the order they appear in the bible
+    #[default]
+    Reference,
+    /// Highest count first, by words if they are counted otherwise verses
+    Count,
+}
+
+#[derive(Parser, Default)]
+pub struct ConcordanceArgs {
+    /// The word or pattern to list. eg. shepherd, /shepherds?/i
+    pattern: String,
+    /// The books, chapters or verses to include. eg. NT, OT, Ge, 1Sa..2Ch, "Ps 119", "Ps 119:1-8"
+    #[arg(short, long, num_args=1..)]
+    include: Vec<String>,
+    /// How to order the lines
+    #[arg(short, long, value_enum, default_value_t = ConcordanceSort::Reference)]
+    sort: ConcordanceSort,
+    /// Number of characters of text to show either side of the word
+    #[arg(short, long, default_value_t = 40)]
+    width: usize,
+}
+
+/// Order for the lines of a concordance
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum ConcordanceSort {
+    /// In the order they appear in the bible
+    #[default]
+    Reference,
+    /// By the words before the keyword, nearest first
+    Left,
+    /// By the words after the keyword
+    Right,
+}
+
+#[derive(Parser, Default)]
+pub struct FindArgs {
+    /// The text to find, as well as it is remembered e.g. "the lamp unto my feet"
+    text: String,
+    /// The books, chapters or verses to include. eg. NT, OT, Ge, 1Sa..2Ch, "Ps 119", "Ps 119:1-8"
+    #[arg(short, long, num_args=1..)]
+    include: Vec<String>,
+    /// Number of the most similar verses to show
+    #[arg(short = 'n', long, default_value_t = 10)]
+    top: usize,
+}
+
+#[derive(Parser, Default)]
+pub struct StatsArgs {
+    /// The books, chapters or verses to include. eg. NT, OT, Ge, 1Sa..2Ch, "Ps 119", "Ps 119:1-8"
+    #[arg(short, long, num_args=1..)]
+    include: Vec<String>,
+    /// Number of the most frequent words to list
+    #[arg(short = 'n', long, default_value_t = 20)]
+    top: usize,
+    /// List the words that occur only once instead of the most frequent
+    #[arg(long)]
+    hapax: bool,
+    /// Also break the counts down by book or testament
+    #[arg(short, long, value_enum)]
+    by: Option<Breakdown>,
+}
+
+/// Groups to break word statistics down by
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Breakdown {
+    Book,
+    /// Old and New Testaments
+    Testament,
 }
 
 #[macro_export]
@@ -64,11 +269,46 @@ macro_rules! oprintln {
     };
 }
 
+mod format;
+pub use format::Format;
+
+mod context;
+
+mod diacritics;
+
+mod highlight;
+pub use highlight::Color;
+
+mod query;
+
+mod rank;
+
+mod spelling;
+
+mod stem;
+
+mod word;
+
+mod reference;
+pub use reference::{Passage, Reference, VerseRef, parse_book_name};
+
 mod details;
 pub use details::details;
 
 mod verse;
-pub use verse::verse;
+pub use verse::{compare, verse};
+
+mod index;
+pub use index::build_index;
 
 mod search;
-pub use search::search;
+pub use search::{search, search_versions};
+
+mod find;
+pub use find::find;
+
+mod concordance;
+pub use concordance::concordance;
+
+mod stats;
+pub use stats::stats;
@@ -1,69 +1,175 @@
 use biblearchive::BARFile;
-use biblearchive_utils::{Args, Command, details, search, verse};
+use biblearchive_utils::{
+    Args, Command, IndexCommand, build_index, compare, concordance, details, find, search,
+    search_versions, stats, verse,
+};
 use clap::{CommandFactory, Parser};
-use std::fs;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
 use std::process::exit;
 
-fn main() {
-    let args = Args::parse();
-    // First see if an explicit path has been specified
-    let mut path = args.file;
-    if path.is_none() {
-        // Try to get the path from the data dir and the version
-        if let Some(dir) = args.datadir {
-            if !fs::exists(&dir).unwrap_or(false) {
+/// Find the BARFile for a version in the data dir
+fn find_version(dir: &Path, version: &str) -> Option<PathBuf> {
+    // Try .bar file extension fist
+    let mut bar_path = dir.to_path_buf();
+    bar_path.push(format!("{}.bar", version));
+    if fs::exists(&bar_path).unwrap_or(false) {
+        return Some(bar_path);
+    }
+    // Try .ibar file extension next
+    let mut ibar_path = dir.to_path_buf();
+    ibar_path.push(format!("{}.ibar", version));
+    if fs::exists(&ibar_path).unwrap_or(false) {
+        return Some(ibar_path);
+    }
+    None
+}
+
+/// Find the versions of all the BARFiles in the data dir
+fn all_versions(dir: &Path) -> Vec<String> {
+    let mut versions: Vec<String> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "bar" || extension == "ibar")
+            })
+            .filter_map(|path| Some(path.file_stem()?.to_string_lossy().to_string()))
+            .collect(),
+        Err(error) => {
+            eprintln!("Cannot read directory {}.", dir.to_string_lossy());
+            eprintln!("{}", error);
+            exit(1);
+        }
+    };
+    // A version can have both a .bar and a .ibar file
+    versions.sort();
+    versions.dedup();
+    versions
+}
+
+/// Get the data dir, exiting if it has not been specified or does not exist
+fn data_dir(datadir: &Option<PathBuf>) -> &PathBuf {
+    match datadir {
+        Some(dir) => {
+            if !fs::exists(dir).unwrap_or(false) {
                 eprintln!(
                     "Path specified for datadir does not exist: {}",
                     dir.to_string_lossy()
                 );
                 exit(1);
             }
-            if let Some(version) = args.ver {
-                // Try .bar file extension fist
-                let mut bar_path = dir.clone();
-                bar_path.push(format!("{}.bar", version));
-                if fs::exists(&bar_path).unwrap_or(false) {
-                    path = Some(bar_path);
-                } else {
-                    // Try .ibar file extension next
-                    let mut ibar_path = dir.clone();
-                    ibar_path.push(format!("{}.ibar", version));
-                    if fs::exists(&ibar_path).unwrap_or(false) {
-                        path = Some(ibar_path);
-                    } else {
-                        eprintln!(
-                            "Cannot find version {} in directory {}.",
-                            version,
-                            dir.to_string_lossy()
-                        );
-                        exit(1);
-                    }
-                }
-            } else {
-                eprintln!("No path to BARFile or version from datadir specified.");
-                exit(1);
-            }
-        } else {
+            dir
+        }
+        None => {
             eprintln!("Path to BARFile not specified.");
             exit(1);
         }
     }
-    let path = path.unwrap();
+}
+
+/// Find the BARFile for a version in the data dir, exiting if it is missing
+fn version_path(dir: &Path, version: &str) -> PathBuf {
+    match find_version(dir, version) {
+        Some(path) => path,
+        None => {
+            eprintln!(
+                "Cannot find version {} in directory {}.",
+                version,
+                dir.to_string_lossy()
+            );
+            exit(1);
+        }
+    }
+}
+
+fn open_bar(path: PathBuf) -> BARFile<File> {
     let bar = BARFile::open(path);
     if let Err(error) = bar {
         eprintln!("Error opening BARFile.");
         eprintln!("{}", error);
         exit(1);
     }
-    let bar = bar.unwrap();
+    bar.unwrap()
+}
+
+fn main() {
+    let args = Args::parse();
+
+    // Comparing versions uses several BARFiles from the data dir
+    if let Some(Command::Verse {
+        verses,
+        compare: versions,
+        layout,
+        width,
+        ..
+    }) = &args.command
+        && !versions.is_empty()
+    {
+        let dir = data_dir(&args.datadir);
+        let bars = versions
+            .iter()
+            .map(|version| (version.clone(), open_bar(version_path(dir, version))))
+            .collect();
+        exit(compare(bars, verses, *layout, *width, args.format));
+    }
+
+    // Searching several versions uses several BARFiles from the data dir
+    if let Some(Command::Search(params)) = &args.command
+        && (params.all_versions || args.ver.as_ref().is_some_and(|ver| ver.contains(',')))
+    {
+        let dir = data_dir(&args.datadir);
+        let versions: Vec<String> = match &args.ver {
+            Some(ver) if !params.all_versions => {
+                ver.split(',').map(|v| v.trim().to_string()).collect()
+            }
+            _ => all_versions(dir),
+        };
+        if versions.is_empty() {
+            eprintln!("No BARFiles found in directory {}.", dir.to_string_lossy());
+            exit(1);
+        }
+        let bars = versions
+            .into_iter()
+            .map(|version| {
+                let path = version_path(dir, &version);
+                (version, path.clone(), open_bar(path))
+            })
+            .collect();
+        exit(search_versions(bars, params, args.format));
+    }
+
+    // First see if an explicit path has been specified
+    let mut path = args.file;
+    if path.is_none() {
+        // Try to get the path from the data dir and the version
+        let dir = data_dir(&args.datadir);
+        if let Some(version) = args.ver {
+            path = Some(version_path(dir, &version));
+        } else {
+            eprintln!("No path to BARFile or version from datadir specified.");
+            exit(1);
+        }
+    }
+    let path = path.unwrap();
+    let bar = open_bar(path.clone());
 
     let status = match &args.command {
         Some(Command::Details { compression }) => {
-            details(bar, *compression);
+            details(bar, *compression, args.format);
             0
         }
-        Some(Command::Verse { verses }) => verse(bar, verses),
-        Some(Command::Search(params)) => search(bar, params),
+        Some(Command::Verse {
+            verses, context, ..
+        }) => verse(bar, verses, *context, args.format),
+        Some(Command::Search(params)) => search(bar, &path, params, args.format),
+        Some(Command::Find(params)) => find(bar, params, args.format),
+        Some(Command::Concordance(params)) => concordance(bar, params, args.format),
+        Some(Command::Stats(params)) => stats(bar, params, args.format),
+        Some(Command::Index {
+            command: IndexCommand::Build,
+        }) => build_index(bar, &path, args.format),
         None => {
             eprintln!("No command specified.");
             let mut cmd = Args::command().bin_name("bar");
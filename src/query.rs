@@ -0,0 +1,296 @@
+use regex::{Regex, RegexBuilder};
+use std::error::Error;
+
+/// A boolean search expression e.g. (grace OR mercy) AND NOT law
+///
+/// Terms next to each other without an operator must both match. NEAR/n
+/// binds tightest, then NOT, then AND, then OR. The operators must be upper
+/// case so that "and", "or" and "not" can still be searched for as words.
+pub enum Query {
+    /// A whole word, matched case-insensitively if it is all lower case
+    Word(String),
+    /// A quoted phrase to be found anywhere in the verse
+    Phrase(String),
+    /// A /regex/ or /regex/i
+    Regex(Regex),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    /// Matches of the two queries within the given number of words of each
+    /// other. Only terms, ORs of terms and other NEARs have positions to
+    /// measure between.
+    Near(Box<Query>, Box<Query>, usize),
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Near(usize),
+    Word(String),
+    Phrase(String),
+    Regex(String, bool),
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' => {
+                chars.next();
+                let mut phrase = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => phrase.push(c),
+                        None => return Err(format!("Missing closing quote after \"{}", phrase)),
+                    }
+                }
+                tokens.push(Token::Phrase(phrase));
+            }
+            '/' => {
+                chars.next();
+                let mut pattern = String::new();
+                loop {
+                    match chars.next() {
+                        Some('/') => break,
+                        Some('\\') => {
+                            // Keep escapes so that \/ does not end the regex
+                            pattern.push('\\');
+                            if let Some(c) = chars.next() {
+                                pattern.push(c);
+                            }
+                        }
+                        Some(c) => pattern.push(c),
+                        None => return Err(format!("Missing closing / after /{}", pattern)),
+                    }
+                }
+                let ignore_case = chars.peek() == Some(&'i');
+                if ignore_case {
+                    chars.next();
+                }
+                tokens.push(Token::Regex(pattern, ignore_case));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                let token = match &word[..] {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => match word.strip_prefix("NEAR/") {
+                        Some(n) => match n.parse() {
+                            Ok(n) => Token::Near(n),
+                            Err(_) => return Err(format!("Invalid distance in {}", word)),
+                        },
+                        None => Token::Word(word),
+                    },
+                };
+                tokens.push(token);
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser over the tokens
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        self.position += 1;
+        self.tokens.get(self.position - 1)
+    }
+
+    fn parse_or(&mut self) -> Result<Query, Box<dyn Error>> {
+        let mut query = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            query = Query::Or(Box::new(query), Box::new(self.parse_and()?));
+        }
+        Ok(query)
+    }
+
+    fn parse_and(&mut self) -> Result<Query, Box<dyn Error>> {
+        let mut query = self.parse_not()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.next();
+                }
+                // Terms next to each other are implicitly ANDed
+                Some(Token::Close) | Some(Token::Or) | None => break,
+                Some(_) => (),
+            }
+            query = Query::And(Box::new(query), Box::new(self.parse_not()?));
+        }
+        Ok(query)
+    }
+
+    fn parse_not(&mut self) -> Result<Query, Box<dyn Error>> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Query::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_near()
+    }
+
+    fn parse_near(&mut self) -> Result<Query, Box<dyn Error>> {
+        let mut query = self.parse_term()?;
+        while let Some(&Token::Near(distance)) = self.peek() {
+            self.next();
+            let other = self.parse_term()?;
+            if !query.has_positions() || !other.has_positions() {
+                return Err("NEAR can only be used between words, phrases and regexps".into());
+            }
+            query = Query::Near(Box::new(query), Box::new(other), distance);
+        }
+        Ok(query)
+    }
+
+    fn parse_term(&mut self) -> Result<Query, Box<dyn Error>> {
+        match self.next() {
+            Some(Token::Open) => {
+                let query = self.parse_or()?;
+                if self.next() != Some(&Token::Close) {
+                    return Err("Missing closing bracket".into());
+                }
+                Ok(query)
+            }
+            Some(Token::Word(word)) => Ok(Query::Word(word.clone())),
+            Some(Token::Phrase(phrase)) => Ok(Query::Phrase(phrase.clone())),
+            Some(Token::Regex(pattern, ignore_case)) => {
+                match RegexBuilder::new(pattern)
+                    .case_insensitive(*ignore_case)
+                    .build()
+                {
+                    Ok(regex) => Ok(Query::Regex(regex)),
+                    Err(_) => Err(format!("Invalid regexp: /{}/", pattern).into()),
+                }
+            }
+            Some(Token::Close) => Err("Unexpected closing bracket".into()),
+            Some(token) => Err(format!("Expected a term but found {:?}", token).into()),
+            None => Err("Unexpected end of query".into()),
+        }
+    }
+}
+
+impl Query {
+    pub fn parse(s: &str) -> Result<Query, Box<dyn Error>> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            position: 0,
+        };
+        let query = parser.parse_or()?;
+        if parser.peek().is_some() {
+            return Err("Unexpected closing bracket".into());
+        }
+        Ok(query)
+    }
+
+    fn has_positions(&self) -> bool {
+        match self {
+            Query::Word(_) | Query::Phrase(_) | Query::Regex(_) | Query::Near(..) => true,
+            Query::Or(a, b) => a.has_positions() && b.has_positions(),
+            Query::And(..) | Query::Not(_) => false,
+        }
+    }
+
+    pub fn has_near(&self) -> bool {
+        match self {
+            Query::Near(..) => true,
+            Query::And(a, b) | Query::Or(a, b) => a.has_near() || b.has_near(),
+            Query::Not(a) => a.has_near(),
+            Query::Word(_) | Query::Phrase(_) | Query::Regex(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fully bracketed form of the query to check the precedence
+    fn show(query: &Query) -> String {
+        match query {
+            Query::Word(word) => word.clone(),
+            Query::Phrase(phrase) => format!("\"{}\"", phrase),
+            Query::Regex(regex) => format!("/{}/", regex.as_str()),
+            Query::And(a, b) => format!("({} AND {})", show(a), show(b)),
+            Query::Or(a, b) => format!("({} OR {})", show(a), show(b)),
+            Query::Not(a) => format!("NOT {}", show(a)),
+            Query::Near(a, b, n) => format!("({} NEAR/{} {})", show(a), n, show(b)),
+        }
+    }
+
+    fn parse(s: &str) -> String {
+        show(&Query::parse(s).unwrap())
+    }
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(
+            parse("(grace OR mercy) AND NOT law"),
+            "((grace OR mercy) AND NOT law)"
+        );
+        assert_eq!(parse("a OR b c AND d"), "(a OR ((b AND c) AND d))");
+        assert_eq!(parse("NOT NOT a OR b"), "(NOT NOT a OR b)");
+        assert_eq!(
+            parse(r#""the Lord" or /prais(e|ed)\/?/i"#),
+            r#"(("the Lord" AND or) AND /prais(e|ed)\/?/)"#
+        );
+        assert_eq!(parse("((a))"), "a");
+        assert_eq!(
+            parse("NOT faith NEAR/5 (works OR deeds) law"),
+            "(NOT (faith NEAR/5 (works OR deeds)) AND law)"
+        );
+    }
+
+    #[test]
+    fn test_parse_query_errors() {
+        for s in [
+            "",
+            "(a OR b",
+            "a OR b)",
+            "a AND",
+            "NOT",
+            "\"a b",
+            "/a(/",
+            "/a",
+            "a OR OR b",
+            "a NEAR/x b",
+            "a NEAR/2 (b c)",
+            "a NEAR/2 NOT b",
+        ] {
+            assert!(Query::parse(s).is_err(), "{}", s);
+        }
+    }
+}
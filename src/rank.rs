@@ -0,0 +1,135 @@
+/// Okapi BM25 parameter for how quickly repeats of a term stop adding to
+/// the score
+const K1: f64 = 1.2;
+/// Okapi BM25 parameter for how much longer documents are penalised
+const B: f64 = 0.75;
+
+/// How often each query term occurs in a document (a verse or a chapter)
+/// and how many words the document has
+#[derive(Default)]
+pub struct TermCounts {
+    counts: Vec<usize>,
+    length: usize,
+}
+
+impl TermCounts {
+    pub fn new(terms: &[String]) -> TermCounts {
+        TermCounts {
+            counts: vec![0; terms.len()],
+            length: 0,
+        }
+    }
+
+    /// Count some more of the document's words
+    pub fn add(&mut self, terms: &[String], words: impl IntoIterator<Item = String>) {
+        for word in words {
+            self.length += 1;
+            if let Some(i) = terms.iter().position(|term| *term == word) {
+                self.counts[i] += 1;
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+}
+
+/// Statistics of all the documents searched, which the scores of the ones
+/// that match depend on
+#[derive(Default)]
+pub struct Corpus {
+    documents: usize,
+    total_length: usize,
+    /// The number of documents each query term is found in
+    document_frequency: Vec<usize>,
+}
+
+impl Corpus {
+    pub fn add(&mut self, document: &TermCounts) {
+        self.documents += 1;
+        self.total_length += document.length;
+        self.document_frequency.resize(document.counts.len(), 0);
+        for (frequency, count) in self.document_frequency.iter_mut().zip(&document.counts) {
+            if *count > 0 {
+                *frequency += 1;
+            }
+        }
+    }
+
+    /// Add the documents of another part of the corpus
+    pub fn merge(&mut self, other: Corpus) {
+        self.documents += other.documents;
+        self.total_length += other.total_length;
+        if self.document_frequency.len() < other.document_frequency.len() {
+            self.document_frequency
+                .resize(other.document_frequency.len(), 0);
+        }
+        for (frequency, other) in self
+            .document_frequency
+            .iter_mut()
+            .zip(other.document_frequency)
+        {
+            *frequency += other;
+        }
+    }
+
+    /// The BM25 score of a document in the corpus. Higher is more relevant.
+    pub fn score(&self, document: &TermCounts) -> f64 {
+        if self.documents == 0 {
+            return 0.0;
+        }
+        let documents = self.documents as f64;
+        let average_length = self.total_length as f64 / documents;
+        let length = document.length as f64;
+        document
+            .counts
+            .iter()
+            .zip(self.document_frequency.iter())
+            .filter(|(count, _)| **count > 0)
+            .map(|(count, frequency)| {
+                let frequency = *frequency as f64;
+                let idf = (1.0 + (documents - frequency + 0.5) / (frequency + 0.5)).ln();
+                let tf = *count as f64;
+                idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * length / average_length))
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::word::words;
+
+    #[test]
+    fn test_score() {
+        let terms = vec!["shepherd".to_string(), "sheep".to_string()];
+        let texts = [
+            "The Lord is my shepherd; I shall not want.",
+            "I am the good shepherd: the good shepherd giveth his life for the sheep.",
+            "All we like sheep have gone astray.",
+            "In the beginning God created the heaven and the earth.",
+        ];
+        let mut corpus = Corpus::default();
+        let documents: Vec<TermCounts> = texts
+            .iter()
+            .map(|text| {
+                let mut counts = TermCounts::new(&terms);
+                counts.add(&terms, words(text));
+                corpus.add(&counts);
+                counts
+            })
+            .collect();
+        let scores: Vec<f64> = documents.iter().map(|d| corpus.score(d)).collect();
+        // Both terms beat one, and no terms scores nothing
+        assert!(scores[1] > scores[0]);
+        assert!(scores[1] > scores[2]);
+        assert!(scores[0] > 0.0 && scores[2] > 0.0);
+        assert_eq!(scores[3], 0.0);
+        // A shorter verse with the term scores higher than a longer one
+        let mut long = TermCounts::new(&terms);
+        long.add(&terms, words(&format!("{} {}", texts[0], texts[3])));
+        assert!(corpus.score(&long) < scores[0]);
+    }
+}
@@ -0,0 +1,765 @@
+use bible_data::{BOOK_ABBREVS, parse_book_abbrev};
+use biblearchive::BARFile;
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Seek};
+use std::str::FromStr;
+
+/// Full book names and common alternatives, in addition to the
+/// abbreviations understood by parse_book_abbrev
+const BOOK_NAMES: [(&str, u8); 72] = [
+    ("Genesis", 1),
+    ("Exodus", 2),
+    ("Leviticus", 3),
+    ("Numbers", 4),
+    ("Deuteronomy", 5),
+    ("Joshua", 6),
+    ("Judges", 7),
+    ("Ruth", 8),
+    ("1 Samuel", 9),
+    ("2 Samuel", 10),
+    ("1 Kings", 11),
+    ("2 Kings", 12),
+    ("1 Chronicles", 13),
+    ("2 Chronicles", 14),
+    ("Ezra", 15),
+    ("Nehemiah", 16),
+    ("Esther", 17),
+    ("Job", 18),
+    ("Psalms", 19),
+    ("Psalm", 19),
+    ("Proverbs", 20),
+    ("Ecclesiastes", 21),
+    ("Song of Solomon", 22),
+    ("Song of Songs", 22),
+    ("Canticles", 22),
+    ("Isaiah", 23),
+    ("Jeremiah", 24),
+    ("Lamentations", 25),
+    ("Ezekiel", 26),
+    ("Daniel", 27),
+    ("Hosea", 28),
+    ("Joel", 29),
+    ("Amos", 30),
+    ("Obadiah", 31),
+    ("Jonah", 32),
+    ("Micah", 33),
+    ("Nahum", 34),
+    ("Habakkuk", 35),
+    ("Zephaniah", 36),
+    ("Haggai", 37),
+    ("Zechariah", 38),
+    ("Malachi", 39),
+    ("Matthew", 40),
+    ("Mark", 41),
+    ("Luke", 42),
+    ("John", 43),
+    ("Acts", 44),
+    ("Acts of the Apostles", 44),
+    ("Romans", 45),
+    ("1 Corinthians", 46),
+    ("2 Corinthians", 47),
+    ("Galatians", 48),
+    ("Ephesians", 49),
+    ("Philippians", 50),
+    ("Colossians", 51),
+    ("1 Thessalonians", 52),
+    ("2 Thessalonians", 53),
+    ("1 Timothy", 54),
+    ("2 Timothy", 55),
+    ("Titus", 56),
+    ("Philemon", 57),
+    ("Hebrews", 58),
+    ("James", 59),
+    ("1 Peter", 60),
+    ("2 Peter", 61),
+    ("1 John", 62),
+    ("2 John", 63),
+    ("3 John", 64),
+    ("Jude", 65),
+    ("Revelation", 66),
+    ("Revelations", 66),
+    ("Revelation of John", 66),
+];
+
+const OLD_TESTAMENT: (u8, u8) = (1, 39);
+const NEW_TESTAMENT: (u8, u8) = (40, 66);
+
+/// The testament the book is in, either "OT" or "NT"
+pub fn testament(book: u8) -> &'static str {
+    if book <= OLD_TESTAMENT.1 { "OT" } else { "NT" }
+}
+
+/// Normalise a book name for comparison. Case and spaces are ignored and
+/// a leading ordinal such as "I", "First" or "1st" becomes a digit.
+fn normalise_book_name(name: &str) -> String {
+    let words: Vec<&str> = name.split_whitespace().collect();
+    let mut key = String::new();
+    for (i, word) in words.iter().enumerate() {
+        let word = word.to_lowercase();
+        let ordinal = match word.as_str() {
+            "i" | "first" | "1st" => "1",
+            "ii" | "second" | "2nd" => "2",
+            "iii" | "third" | "3rd" => "3",
+            _ => "",
+        };
+        if i == 0 && words.len() > 1 && !ordinal.is_empty() {
+            key.push_str(ordinal);
+        } else {
+            key.push_str(&word);
+        }
+    }
+    key
+}
+
+/// Find the number of the book with the given name or abbreviation
+pub fn parse_book_name(name: &str) -> Option<u8> {
+    let key = normalise_book_name(name);
+    if let Some((_, book)) = BOOK_NAMES
+        .iter()
+        .find(|(n, _)| normalise_book_name(n) == key)
+    {
+        return Some(*book);
+    }
+    // Try the abbreviation as given and then with any spaces removed
+    // so that "1 Jn" is treated the same as "1Jn"
+    let collapsed: String = name.split_whitespace().collect();
+    parse_book_abbrev(name.trim())
+        .or_else(|| parse_book_abbrev(&collapsed))
+        .map(|book| (book + 1) as u8)
+}
+
+/// Split a reference such as "Song of Solomon 2:1" or "1 John 3:16" into
+/// the number of its book and the chapter and verse part, if there is one.
+/// Either "." or ":" may separate the chapter and verse, and the book may
+/// be abbreviated with a full stop e.g. "Ps. 23:1".
+fn tokenize_reference(reference: &str) -> Result<(u8, Option<String>), String> {
+    let reference = reference.trim();
+    // The book name runs up to its last letter, which allows for both
+    // spaces and numeric prefixes in the name
+    let end = match reference.rfind(|c: char| c.is_alphabetic()) {
+        Some(i) => i + reference[i..].chars().next().map_or(1, |c| c.len_utf8()),
+        None => return Err(format!("Missing book name in reference: {}", reference)),
+    };
+    let (name, rest) = reference.split_at(end);
+    // Only a numeric prefix may come before the letters of the name
+    let name_start = name.find(|c: char| c.is_alphabetic()).unwrap_or(0);
+    if name[name_start..].contains(|c: char| !c.is_alphabetic() && !c.is_whitespace()) {
+        return Err(format!(
+            "Invalid chapter and verse in reference: {}",
+            reference
+        ));
+    }
+    let book = match parse_book_name(name) {
+        Some(book) => book,
+        None => return Err(format!("Unknown book in reference: {}", reference)),
+    };
+    let rest = rest.strip_prefix('.').unwrap_or(rest);
+    let rest: String = rest
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| if c == '.' { ':' } else { c })
+        .collect();
+    if rest.is_empty() {
+        return Ok((book, None));
+    }
+    if !rest.chars().all(|c| c.is_ascii_digit() || ":-".contains(c)) {
+        return Err(format!(
+            "Invalid chapter and verse in reference: {}",
+            reference
+        ));
+    }
+    Ok((book, Some(rest)))
+}
+
+fn parse_number(s: &str, what: &str) -> Result<u8, String> {
+    match s.trim().parse() {
+        Ok(0) | Err(_) => Err(format!("Invalid {}: {}", what, s)),
+        Ok(n) => Ok(n),
+    }
+}
+
+fn parse_chapter_verse(s: &str) -> Result<(u8, u8), String> {
+    let refs: Vec<&str> = s.split(":").collect();
+    if refs.len() != 2 {
+        return Err(format!("Unexpected chapter:verse : {}", s));
+    }
+    Ok((
+        parse_number(refs[0], "chapter")?,
+        parse_number(refs[1], "verse")?,
+    ))
+}
+
+/// A single verse
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VerseRef {
+    pub book: u8,
+    pub chapter: u8,
+    pub verse: u8,
+}
+
+impl VerseRef {
+    pub fn new(book: u8, chapter: u8, verse: u8) -> VerseRef {
+        VerseRef {
+            book,
+            chapter,
+            verse,
+        }
+    }
+
+    /// The abbreviated name of the book
+    pub fn book_abbrev(&self) -> &'static str {
+        BOOK_ABBREVS[self.book as usize - 1]
+    }
+}
+
+impl fmt::Display for VerseRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}:{}", self.book_abbrev(), self.chapter, self.verse)
+    }
+}
+
+/// A reference to a contiguous part of the bible. This can be a book or
+/// range of books, a chapter or range of chapters within a book, or a
+/// verse or range of verses within a book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reference {
+    /// Whole books e.g. "Ge", "1Sa..2Ch", "NT"
+    Book { start: u8, end: u8 },
+    /// Whole chapters e.g. "Ps 23", "Ps 120-134"
+    Chapter { book: u8, start: u8, end: u8 },
+    /// Verses e.g. "Ge 1:1", "Ge 1:1-5", "Ge 1:31-2:3"
+    Verse { start: VerseRef, end: VerseRef },
+}
+
+impl Reference {
+    /// Parse a single reference. Comma separated lists are parsed with
+    /// Passage instead.
+    pub fn parse(s: &str) -> Result<Reference, String> {
+        let s = s.trim();
+        match s {
+            "OT" => {
+                return Ok(Reference::Book {
+                    start: OLD_TESTAMENT.0,
+                    end: OLD_TESTAMENT.1,
+                });
+            }
+            "NT" => {
+                return Ok(Reference::Book {
+                    start: NEW_TESTAMENT.0,
+                    end: NEW_TESTAMENT.1,
+                });
+            }
+            _ => (),
+        }
+        if let Some((first, last)) = s.split_once("..") {
+            let start = parse_book_name(first);
+            let end = parse_book_name(last);
+            return match (start, end) {
+                (Some(start), Some(end)) if start <= end => Ok(Reference::Book { start, end }),
+                (Some(_), Some(_)) => {
+                    Err(format!("Invalid range: {}. {} is after {}", s, first, last))
+                }
+                _ => Err(format!("Invalid range: {}", s)),
+            };
+        }
+        if s.contains(",") {
+            return Err(format!("Unexpected list in reference: {}", s));
+        }
+        match tokenize_reference(s)? {
+            (book, None) => Ok(Reference::Book {
+                start: book,
+                end: book,
+            }),
+            (book, Some(chapter_verse)) => Reference::parse_in_book(&chapter_verse, book, None),
+        }
+    }
+
+    /// Parse the chapter and verse part of a reference in the given book.
+    /// If a chapter is given then a bare number is a verse in that chapter,
+    /// otherwise it is a whole chapter.
+    fn parse_in_book(s: &str, book: u8, chapter: Option<u8>) -> Result<Reference, String> {
+        let (first, last) = match s.split_once("-") {
+            Some((first, last)) => (first, Some(last)),
+            None => (s, None),
+        };
+        let start = if first.contains(":") {
+            parse_chapter_verse(first)?
+        } else if let Some(c) = chapter {
+            (c, parse_number(first, "verse")?)
+        } else {
+            let start = parse_number(first, "chapter")?;
+            let end = match last {
+                Some(last) if last.contains(":") => {
+                    // Whole chapters running into part of another one
+                    let (c, v) = parse_chapter_verse(last)?;
+                    return Reference::verses(book, (start, 1), (c, v), s);
+                }
+                Some(last) => parse_number(last, "chapter")?,
+                None => start,
+            };
+            if end < start {
+                return Err(format!("Invalid range: {}. End is before start", s));
+            }
+            return Ok(Reference::Chapter { book, start, end });
+        };
+        let end = match last {
+            Some(last) if last.contains(":") => parse_chapter_verse(last)?,
+            Some(last) => (start.0, parse_number(last, "verse")?),
+            None => start,
+        };
+        Reference::verses(book, start, end, s)
+    }
+
+    fn verses(book: u8, start: (u8, u8), end: (u8, u8), s: &str) -> Result<Reference, String> {
+        if end < start {
+            return Err(format!("Invalid range: {}. End is before start", s));
+        }
+        Ok(Reference::Verse {
+            start: VerseRef::new(book, start.0, start.1),
+            end: VerseRef::new(book, end.0, end.1),
+        })
+    }
+
+    /// The first verse covered by the reference
+    pub fn start(&self) -> VerseRef {
+        match *self {
+            Reference::Book { start, .. } => VerseRef::new(start, 1, 1),
+            Reference::Chapter { book, start, .. } => VerseRef::new(book, start, 1),
+            Reference::Verse { start, .. } => start,
+        }
+    }
+
+    /// The last verse covered by the reference. As the number of chapters
+    /// and verses is not known without an archive, u8::MAX stands for the
+    /// last chapter of a book or the last verse of a chapter.
+    pub fn end(&self) -> VerseRef {
+        match *self {
+            Reference::Book { end, .. } => VerseRef::new(end, u8::MAX, u8::MAX),
+            Reference::Chapter { book, end, .. } => VerseRef::new(book, end, u8::MAX),
+            Reference::Verse { end, .. } => end,
+        }
+    }
+
+    /// Whether the verse is part of the reference
+    pub fn contains(&self, verse: &VerseRef) -> bool {
+        self.start() <= *verse && *verse <= self.end()
+    }
+
+    /// Whether any of the book is part of the reference
+    pub fn includes_book(&self, book: u8) -> bool {
+        self.start().book <= book && book <= self.end().book
+    }
+
+    /// Whether the whole of the book is part of the reference
+    pub fn covers_book(&self, book: u8) -> bool {
+        self.contains(&VerseRef::new(book, 1, 1))
+            && self.contains(&VerseRef::new(book, u8::MAX, u8::MAX))
+    }
+
+    /// Whether any of the chapter is part of the reference
+    pub fn includes_chapter(&self, book: u8, chapter: u8) -> bool {
+        let (start, end) = (self.start(), self.end());
+        (start.book, start.chapter) <= (book, chapter) && (book, chapter) <= (end.book, end.chapter)
+    }
+
+    /// Whether the whole of the chapter is part of the reference
+    pub fn covers_chapter(&self, book: u8, chapter: u8) -> bool {
+        self.contains(&VerseRef::new(book, chapter, 1))
+            && self.contains(&VerseRef::new(book, chapter, u8::MAX))
+    }
+
+    /// Fetch the text of every verse covered by the reference from the
+    /// archive, in order. Books and chapters missing from the archive are
+    /// skipped.
+    pub fn verses_in<T: Read + Seek>(
+        &self,
+        bar: &BARFile<T>,
+    ) -> Result<Vec<(VerseRef, String)>, Box<dyn Error>> {
+        let mut verses = Vec::new();
+        let (start, end) = (self.start(), self.end());
+        for b in start.book..=end.book {
+            let book = match bar.book(b) {
+                Some(book) => book,
+                None => continue,
+            };
+            let first_chapter = if b == start.book { start.chapter } else { 1 };
+            let last_chapter = if b == end.book { end.chapter } else { u8::MAX };
+            for c in first_chapter..=last_chapter.min(book.number_of_chapters()) {
+                let chapt = match book.chapter(c) {
+                    Some(chapt) => chapt,
+                    None => continue,
+                };
+                let first = if (b, c) == (start.book, start.chapter) {
+                    start.verse
+                } else {
+                    1
+                };
+                let last = if (b, c) == (end.book, end.chapter) {
+                    end.verse
+                } else {
+                    u8::MAX
+                };
+                for (v, text) in chapt.enumerated_verses() {
+                    if (first..=last).contains(&v) {
+                        verses.push((VerseRef::new(b, c, v), text));
+                    }
+                }
+            }
+        }
+        // Ranges stop at the end of the chapter or book, wherever that is,
+        // but the book, chapter or verse they start from has to be there
+        let is_found = match *self {
+            Reference::Book { start, end } => start != end || !verses.is_empty(),
+            Reference::Chapter { book, start, .. } => verses
+                .first()
+                .is_some_and(|(r, _)| (r.book, r.chapter) == (book, start)),
+            Reference::Verse { start, .. } => verses.first().is_some_and(|(r, _)| *r == start),
+        };
+        if !is_found {
+            return Err(format!("{} is not in the archive", self).into());
+        }
+        Ok(verses)
+    }
+
+    fn book(&self) -> Option<u8> {
+        match *self {
+            Reference::Book { start, end } if start == end => Some(start),
+            Reference::Book { .. } => None,
+            Reference::Chapter { book, .. } => Some(book),
+            Reference::Verse { start, .. } => Some(start.book),
+        }
+    }
+
+    /// Write the chapter and verse part of the reference, leaving out the
+    /// chapter of the start if it is already known from the context
+    fn fmt_chapter_verse(&self, f: &mut fmt::Formatter<'_>, chapter: Option<u8>) -> fmt::Result {
+        match *self {
+            Reference::Book { .. } => Ok(()),
+            Reference::Chapter { start, end, .. } if start == end => write!(f, "{}", start),
+            Reference::Chapter { start, end, .. } => write!(f, "{}-{}", start, end),
+            Reference::Verse { start, end } => {
+                if chapter == Some(start.chapter) {
+                    write!(f, "{}", start.verse)?;
+                } else {
+                    write!(f, "{}:{}", start.chapter, start.verse)?;
+                }
+                if end == start {
+                    Ok(())
+                } else if end.chapter == start.chapter {
+                    write!(f, "-{}", end.verse)
+                } else {
+                    write!(f, "-{}:{}", end.chapter, end.verse)
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Reference::Book { start, end } if (start, end) == OLD_TESTAMENT => write!(f, "OT"),
+            Reference::Book { start, end } if (start, end) == NEW_TESTAMENT => write!(f, "NT"),
+            Reference::Book { start, end } if start == end => {
+                write!(f, "{}", BOOK_ABBREVS[start as usize - 1])
+            }
+            Reference::Book { start, end } => write!(
+                f,
+                "{}..{}",
+                BOOK_ABBREVS[start as usize - 1],
+                BOOK_ABBREVS[end as usize - 1]
+            ),
+            _ => {
+                let book = self.start().book;
+                write!(f, "{} ", BOOK_ABBREVS[book as usize - 1])?;
+                self.fmt_chapter_verse(f, None)
+            }
+        }
+    }
+}
+
+impl PartialOrd for Reference {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Reference {
+    /// References are ordered by where they start and then by where they end
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.start()
+            .cmp(&other.start())
+            .then_with(|| self.end().cmp(&other.end()))
+    }
+}
+
+impl FromStr for Reference {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Reference::parse(s)
+    }
+}
+
+/// A list of references such as "Jn 3:16,18,20-22" or "Ps 23, Ro 8:28"
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Passage(pub Vec<Reference>);
+
+impl Passage {
+    /// Parse a comma separated list of references. An item without a book
+    /// name is in the book of the item before it. A bare number is a verse
+    /// if the item before it gave verses, otherwise it is a whole chapter.
+    pub fn parse(s: &str) -> Result<Passage, String> {
+        let mut references = Vec::new();
+        let mut context: Option<Reference> = None;
+        for item in s.split(",") {
+            let reference = if item.contains(|c: char| c.is_alphabetic()) {
+                Reference::parse(item)?
+            } else {
+                let book = context.and_then(|r| r.book());
+                let book = match book {
+                    Some(book) => book,
+                    None => return Err(format!("Missing book name in reference: {}", s)),
+                };
+                let chapter = match context {
+                    Some(Reference::Verse { end, .. }) => Some(end.chapter),
+                    _ => None,
+                };
+                let item: String = item
+                    .chars()
+                    .filter(|c| !c.is_whitespace())
+                    .map(|c| if c == '.' { ':' } else { c })
+                    .collect();
+                Reference::parse_in_book(&item, book, chapter)?
+            };
+            context = Some(reference);
+            references.push(reference);
+        }
+        Ok(Passage(references))
+    }
+
+    pub fn references(&self) -> &[Reference] {
+        &self.0
+    }
+
+    /// Whether the verse is part of any of the references
+    pub fn contains(&self, verse: &VerseRef) -> bool {
+        self.0.iter().any(|r| r.contains(verse))
+    }
+
+    /// Fetch the text of every verse covered by the passage from the
+    /// archive, in the order the references were given
+    pub fn verses_in<T: Read + Seek>(
+        &self,
+        bar: &BARFile<T>,
+    ) -> Result<Vec<(VerseRef, String)>, Box<dyn Error>> {
+        let mut verses = Vec::new();
+        for reference in self.0.iter() {
+            verses.append(&mut reference.verses_in(bar)?);
+        }
+        Ok(verses)
+    }
+}
+
+impl fmt::Display for Passage {
+    /// Write the references as a list, leaving out book names and chapters
+    /// where they can be taken from the reference before
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut previous: Option<&Reference> = None;
+        for reference in self.0.iter() {
+            let book = reference.book();
+            let chapter = match (previous, reference) {
+                (Some(Reference::Verse { end, .. }), Reference::Verse { .. }) => Some(end.chapter),
+                _ => None,
+            };
+            let is_continued = match (previous, reference) {
+                (_, Reference::Book { .. }) => false,
+                // A bare chapter would be read as a verse after verses
+                (Some(Reference::Verse { .. }), Reference::Chapter { .. }) => false,
+                (Some(p), _) => book.is_some() && p.book() == book,
+                (None, _) => false,
+            };
+            if previous.is_some() {
+                write!(f, ",")?;
+                if !is_continued {
+                    write!(f, " ")?;
+                }
+            }
+            if is_continued {
+                reference.fmt_chapter_verse(f, chapter)?;
+            } else {
+                write!(f, "{}", reference)?;
+            }
+            previous = Some(reference);
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Passage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Passage::parse(s)
+    }
+}
+
+impl<'a> IntoIterator for &'a Passage {
+    type Item = &'a Reference;
+    type IntoIter = std::slice::Iter<'a, Reference>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    fn barfile() -> BARFile<File> {
+        BARFile::open("tests/data/KJV.ibar").unwrap()
+    }
+
+    #[test]
+    fn test_tokenize_reference() {
+        assert_eq!(
+            tokenize_reference("Song of Solomon 2:1").unwrap(),
+            (22, Some("2:1".to_string()))
+        );
+        assert_eq!(
+            tokenize_reference("1 John 3:16").unwrap(),
+            (62, Some("3:16".to_string()))
+        );
+        assert_eq!(
+            tokenize_reference("II Kings 2:11").unwrap(),
+            (12, Some("2:11".to_string()))
+        );
+        assert_eq!(
+            tokenize_reference(" Ps 119.1 ").unwrap(),
+            (19, Some("119:1".to_string()))
+        );
+        assert_eq!(
+            tokenize_reference("Ps. 23:1").unwrap(),
+            (19, Some("23:1".to_string()))
+        );
+        assert_eq!(
+            tokenize_reference("1 Cor. 13:4").unwrap(),
+            (46, Some("13:4".to_string()))
+        );
+        assert_eq!(tokenize_reference("Jude.").unwrap(), (65, None));
+        assert_eq!(tokenize_reference("Genesis").unwrap(), (1, None));
+        assert!(tokenize_reference("3:16").is_err());
+        assert!(tokenize_reference("Nowhere 3:16").is_err());
+        assert!(tokenize_reference("Ps 3:16a").is_err());
+    }
+
+    #[test]
+    fn test_parse_reference() {
+        assert_eq!(
+            Reference::parse("Ps 120-134").unwrap(),
+            Reference::Chapter {
+                book: 19,
+                start: 120,
+                end: 134
+            }
+        );
+        assert_eq!(
+            Reference::parse("Psalm 22:31-23:2").unwrap(),
+            Reference::Verse {
+                start: VerseRef::new(19, 22, 31),
+                end: VerseRef::new(19, 23, 2)
+            }
+        );
+        assert_eq!(
+            Reference::parse("Matthew..John").unwrap(),
+            Reference::Book { start: 40, end: 43 }
+        );
+        assert!(Reference::parse("Ps 3:16-2:1").is_err());
+        assert!(Reference::parse("Ps 3-2").is_err());
+        assert!(Reference::parse("Ps 0").is_err());
+        assert!(Reference::parse("Rev..Mt").is_err());
+    }
+
+    #[test]
+    fn test_parse_passage() {
+        let passage = Passage::parse("Psalms 119:1,3,5-8, Ps 120-121, Rev 1").unwrap();
+        assert_eq!(
+            passage.references(),
+            &[
+                Reference::parse("Ps 119:1").unwrap(),
+                Reference::parse("Ps 119:3").unwrap(),
+                Reference::parse("Ps 119:5-8").unwrap(),
+                Reference::parse("Ps 120-121").unwrap(),
+                Reference::parse("Rev 1").unwrap(),
+            ]
+        );
+        assert_eq!(passage.to_string(), "Ps 119:1,3,5-8, Ps 120-121, Rev 1");
+        assert_eq!(Passage::parse(&passage.to_string()).unwrap(), passage);
+        assert!(Passage::parse("119:1").is_err());
+        assert!(Passage::parse("NT, 5").is_err());
+    }
+
+    #[test]
+    fn test_ordering_and_containment() {
+        let chapter = Reference::parse("Ps 23").unwrap();
+        let verse = Reference::parse("Ps 23:1").unwrap();
+        let book = Reference::parse("Ps").unwrap();
+        let mut references = [chapter, Reference::parse("NT").unwrap(), verse, book];
+        references.sort();
+        assert_eq!(references[..3], [book, verse, chapter]);
+        assert!(chapter.contains(&VerseRef::new(19, 23, 6)));
+        assert!(!verse.contains(&VerseRef::new(19, 23, 2)));
+        assert!(verse.includes_chapter(19, 23));
+        assert!(!verse.covers_chapter(19, 23));
+        assert!(book.covers_chapter(19, 23));
+        assert!(Reference::parse("NT").unwrap().includes_book(66));
+    }
+
+    #[test]
+    fn test_verses_in() {
+        let verses = Reference::parse("2 Peter 3:18")
+            .unwrap()
+            .verses_in(&barfile())
+            .unwrap();
+        assert_eq!(
+            verses,
+            vec![(
+                VerseRef::new(61, 3, 18),
+                "But grow in grace, and in the knowledge of our Lord and Saviour Jesus Christ. To him be glory both now and for ever. Amen.".to_string()
+            )]
+        );
+        let verses = Reference::parse("Ps 119")
+            .unwrap()
+            .verses_in(&barfile())
+            .unwrap();
+        assert_eq!(verses.len(), 176);
+        let verses = Reference::parse("Ps 117:1-5")
+            .unwrap()
+            .verses_in(&barfile())
+            .unwrap();
+        let refs: Vec<VerseRef> = verses.into_iter().map(|(verse_ref, _)| verse_ref).collect();
+        assert_eq!(
+            refs,
+            vec![VerseRef::new(19, 117, 1), VerseRef::new(19, 117, 2)]
+        );
+        for missing in ["Ps 117:5", "Ps 117:3-5", "Ps 151:1", "Ps 151", "Ps 151-152"] {
+            let error = Reference::parse(missing)
+                .unwrap()
+                .verses_in(&barfile())
+                .unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("{} is not in the archive", missing)
+            );
+        }
+        let verses = Reference::parse("Ps 150-152")
+            .unwrap()
+            .verses_in(&barfile())
+            .unwrap();
+        assert_eq!(verses.len(), 6);
+    }
+}
@@ -1,44 +1,95 @@
-use super::SearchArgs;
-use bible_data::{BOOK_ABBREVS, BibleBookOrChapter, parse_book_abbrev};
+use super::context::Context;
+use super::diacritics::{fold, fold_pattern};
+use super::format::{Table, VERSE_COLUMNS, Value, verse_row};
+use super::highlight::{Highlighter, Markers};
+use super::index::{Candidates, Index, both, either};
+use super::query::Query;
+use super::rank::{Corpus, TermCounts};
+use super::reference::testament;
+use super::spelling::Spellings;
+use super::stem::{stem, stem_regexp};
+use super::word::{is_lower_case, is_wildcard, is_word_char, word_regexp, words};
+use super::{CountSort, Format, GroupBy, Passage, RankBy, Reference, SearchArgs, VerseRef};
 use biblearchive::BARFile;
 use regex::{Regex, RegexBuilder};
 use std::{
-    collections::HashMap,
+    cmp::Reverse,
+    collections::{BTreeMap, HashSet},
     error::Error,
     io::{Read, Seek},
-    ops::RangeInclusive,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
 };
 
-// Filters
-fn exclude_all() -> impl Fn(bool, u32) -> bool {
-    |_, _| false
+/// The books, chapters and verses to search. Each reference includes or
+/// excludes verses, with later references taking priority. If the first
+/// reference is an include then everything else is initially excluded.
+pub struct Scope {
+    references: Vec<(bool, Reference)>,
 }
 
-fn exclude_item(book_number: u32) -> impl Fn(bool, u32) -> bool {
-    move |input, book| match input {
-        false => false,
-        true => book != book_number,
+impl Scope {
+    pub fn parse(include: &[String]) -> Result<Scope, Box<dyn Error>> {
+        let mut references = Vec::new();
+        for m in include.iter() {
+            let is_exclude = m.starts_with("!");
+            let s = if is_exclude { &m[1..] } else { &m[..] };
+            let passage = match Passage::parse(s) {
+                Ok(passage) => passage,
+                Err(error) => {
+                    return Err(format!("Invalid value for --include: {}. {}", m, error).into());
+                }
+            };
+            for reference in passage.references() {
+                references.push((!is_exclude, *reference));
+            }
+        }
+        Ok(Scope { references })
     }
-}
 
-fn exclude_item_range(book_range: RangeInclusive<u32>) -> impl Fn(bool, u32) -> bool {
-    move |input, book| match input {
-        false => false,
-        true => !book_range.contains(&book),
+    fn default(&self) -> bool {
+        self.references
+            .first()
+            .is_none_or(|(is_include, _)| !is_include)
     }
-}
 
-fn include_item(book_number: u32) -> impl Fn(bool, u32) -> bool {
-    move |input, book| match input {
-        true => true,
-        false => book == book_number,
+    /// Whether any of the book might be included
+    pub fn includes_book(&self, book: u8) -> bool {
+        self.references
+            .iter()
+            .fold(self.default(), |acc, (is_include, r)| {
+                if r.covers_book(book) {
+                    *is_include
+                } else {
+                    acc || (*is_include && r.includes_book(book))
+                }
+            })
     }
-}
 
-fn include_item_range(book_range: RangeInclusive<u32>) -> impl Fn(bool, u32) -> bool {
-    move |input, book| match input {
-        true => true,
-        false => book_range.contains(&book),
+    /// Whether any of the chapter might be included
+    pub fn includes_chapter(&self, book: u8, chapter: u8) -> bool {
+        self.references
+            .iter()
+            .fold(self.default(), |acc, (is_include, r)| {
+                if r.covers_chapter(book, chapter) {
+                    *is_include
+                } else {
+                    acc || (*is_include && r.includes_chapter(book, chapter))
+                }
+            })
+    }
+
+    pub fn includes_verse(&self, verse: &VerseRef) -> bool {
+        self.references
+            .iter()
+            .fold(self.default(), |acc, (is_include, r)| {
+                if r.contains(verse) { *is_include } else { acc }
+            })
     }
 }
 
@@ -51,28 +102,407 @@ fn match_regex(regex: Regex) -> impl Fn(&str) -> bool {
     move |verse| regex.is_match(verse)
 }
 
-fn match_word(word: &str) -> Box<dyn Fn(&str) -> bool> {
+/// A filter on the text of a verse. Filters are shared between the threads
+/// searching each book.
+type Filter = Box<dyn Fn(&str) -> bool + Send + Sync>;
+
+fn match_word(word: &str) -> Filter {
     match word_regexp(word) {
         Some(re) => Box::new(match_regex(re)),
         None => Box::new(|_| false),
     }
 }
 
-fn word_regexp(word: &str) -> Option<Regex> {
-    // Get rid of any non alpha-numerics
-    let safe = word.replace(|c: char| !c.is_ascii_alphanumeric() && c != ' ', "");
-    // If word is all lower-case assume we want case-insensitive search
-    let ignore_case = safe.chars().all(|c| c.is_ascii_lowercase());
-    // Convert to a regex that will match on word boundaries
-    let regex = format!(r"\b{}\b", safe);
-    RegexBuilder::new(&regex)
-        .case_insensitive(ignore_case)
-        .build()
-        .ok()
+/// A filter for the query, given the verse and the verse following it.
+/// Returns the spans matched by any NEAR operators, or None if the query does
+/// not match. Spans are byte ranges of the verse, or of the verse joined to
+/// the following verse with a space when NEAR runs across verses.
+type QueryFilter = Box<dyn Fn(&str, Option<&str>) -> Option<Vec<Range<usize>>> + Send + Sync>;
+
+/// How words and phrases are matched, beyond matching them exactly
+#[derive(Clone, Copy)]
+struct WordMatching<'a> {
+    /// Match all the inflections of the word
+    stem: bool,
+    /// Match the other spellings of the word
+    spellings: Option<&'a Spellings>,
+    /// Match the word with or without diacritics
+    ignore_diacritics: bool,
+    /// Match phrases ignoring case, punctuation and spacing
+    normalize_phrases: bool,
+}
+
+impl WordMatching<'_> {
+    /// Whether words and phrases are only matched as they are
+    fn is_exact(&self) -> bool {
+        !self.stem && self.spellings.is_none() && !self.ignore_diacritics && !self.normalize_phrases
+    }
+
+    /// Regex for a phrase with its words spelt differently, or when
+    /// normalizing phrases for its words in any case with any punctuation
+    /// and spacing between them. None if the phrase is matched as it is.
+    fn phrase_pattern(&self, phrase: &str) -> Option<Regex> {
+        if !self.normalize_phrases {
+            return self.spellings.and_then(|s| s.phrase_regexp(phrase));
+        }
+        let words: Vec<String> = phrase
+            .split(|c: char| !is_word_char(c))
+            .filter(|word| !word.is_empty())
+            .map(|word| {
+                let variants = match self.spellings {
+                    Some(spellings) => spellings.variants(word),
+                    None => vec![word.to_string()],
+                };
+                let escaped: Vec<String> = variants.iter().map(|v| regex::escape(v)).collect();
+                format!("(?:{})", escaped.join("|"))
+            })
+            .collect();
+        if words.is_empty() {
+            return None;
+        }
+        let mut pattern = words.join(r"\W+");
+        if self.ignore_diacritics {
+            pattern = fold_pattern(&fold(&pattern));
+        }
+        RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .build()
+            .ok()
+    }
+
+    /// Regex for a whole word, or for all its inflections when stemming,
+    /// along with its other spellings if there are any
+    fn pattern(&self, word: &str) -> Option<Regex> {
+        if !self.ignore_diacritics {
+            return self.unfolded_pattern(word);
+        }
+        let regex = self.unfolded_pattern(&fold(word))?;
+        RegexBuilder::new(&fold_pattern(regex.as_str()))
+            .case_insensitive(is_lower_case(word))
+            .build()
+            .ok()
+    }
+
+    fn unfolded_pattern(&self, word: &str) -> Option<Regex> {
+        // Wildcards already match the inflections they are meant to
+        let pattern = |word: &str| match self.stem && !is_wildcard(word) {
+            true => stem_regexp(word),
+            false => word_regexp(word),
+        };
+        let variants = match self.spellings {
+            Some(spellings) => spellings.variants(word),
+            None => return pattern(word),
+        };
+        if variants.len() == 1 {
+            return pattern(word);
+        }
+        let patterns: Vec<String> = variants
+            .iter()
+            .filter_map(|variant| pattern(variant))
+            .map(|regex| format!("(?:{})", regex.as_str()))
+            .collect();
+        RegexBuilder::new(&patterns.join("|"))
+            .case_insensitive(is_lower_case(word))
+            .build()
+            .ok()
+    }
+
+    /// The verses the index says may contain the word, its inflections when
+    /// stemming or its other spellings
+    fn candidates(&self, index: &Index, word: &str) -> Candidates {
+        if self.ignore_diacritics {
+            // The words in the index keep their diacritics, so have to be
+            // folded to compare
+            if word.split_whitespace().count() != 1 {
+                return None;
+            }
+            let regex = self.unfolded_pattern(&fold(&word.to_lowercase()))?;
+            return Some(index.matching(&regex, true));
+        }
+        let variants = match self.spellings {
+            Some(spellings) => spellings.variants(word),
+            None => vec![word.to_string()],
+        };
+        variants
+            .iter()
+            .map(|variant| match self.stem && !is_wildcard(variant) {
+                true => index.stem(variant),
+                false => index.word(variant),
+            })
+            .reduce(either)
+            .flatten()
+    }
+
+    /// Match the inflections or other spellings of the words in the query
+    fn expand(&self, query: Query) -> Query {
+        let expand = |query: Box<Query>| Box::new(self.expand(*query));
+        match query {
+            Query::Word(word) => match self.pattern(&word) {
+                Some(regex) => Query::Regex(regex),
+                None => Query::Word(word),
+            },
+            Query::Phrase(phrase) => match self.phrase_pattern(&phrase) {
+                Some(regex) => Query::Regex(regex),
+                None => Query::Phrase(phrase),
+            },
+            Query::And(a, b) => Query::And(expand(a), expand(b)),
+            Query::Or(a, b) => Query::Or(expand(a), expand(b)),
+            Query::Not(a) => Query::Not(expand(a)),
+            Query::Near(a, b, n) => Query::Near(expand(a), expand(b), n),
+            query => query,
+        }
+    }
+}
+
+/// Combine the filters for each term of the query
+fn match_query(query: Query, across_verses: bool) -> QueryFilter {
+    match query {
+        Query::Word(word) => {
+            let f = match_word(&word);
+            Box::new(move |verse, _| f(verse).then(Vec::new))
+        }
+        Query::Phrase(phrase) => {
+            let f = match_phrase(phrase);
+            Box::new(move |verse, _| f(verse).then(Vec::new))
+        }
+        Query::Regex(regex) => {
+            let f = match_regex(regex);
+            Box::new(move |verse, _| f(verse).then(Vec::new))
+        }
+        Query::And(a, b) => {
+            let (a, b) = (
+                match_query(*a, across_verses),
+                match_query(*b, across_verses),
+            );
+            Box::new(move |verse, next| {
+                let mut spans = a(verse, next)?;
+                spans.extend(b(verse, next)?);
+                Some(spans)
+            })
+        }
+        Query::Or(a, b) => {
+            let (a, b) = (
+                match_query(*a, across_verses),
+                match_query(*b, across_verses),
+            );
+            Box::new(move |verse, next| match (a(verse, next), b(verse, next)) {
+                (None, None) => None,
+                (a, b) => Some([a.unwrap_or_default(), b.unwrap_or_default()].concat()),
+            })
+        }
+        Query::Not(a) => {
+            let a = match_query(*a, across_verses);
+            Box::new(move |verse, next| match a(verse, next) {
+                Some(_) => None,
+                None => Some(Vec::new()),
+            })
+        }
+        near => {
+            let f = find_spans(near);
+            Box::new(move |verse, next| {
+                let spans: Vec<Range<usize>> = match next {
+                    Some(next) if across_verses => f(&format!("{} {}", verse, next)),
+                    _ => f(verse),
+                };
+                // Spans starting in the next verse are found with that verse
+                let spans: Vec<Range<usize>> = spans
+                    .into_iter()
+                    .filter(|span| span.start < verse.len())
+                    .collect();
+                (!spans.is_empty()).then_some(spans)
+            })
+        }
+    }
+}
+
+/// Finds where the words, phrases and regexps of a query match in the text
+type SpanFinder = Box<dyn Fn(&str) -> Vec<Range<usize>> + Send + Sync>;
+
+/// Find where the words, phrases and regexps of the query match, for
+/// measuring the distance between them
+fn find_spans(query: Query) -> SpanFinder {
+    match query {
+        Query::Word(word) => match word_regexp(&word) {
+            Some(re) => Box::new(move |text| re.find_iter(text).map(|m| m.range()).collect()),
+            None => Box::new(|_| Vec::new()),
+        },
+        Query::Phrase(phrase) => Box::new(move |text| {
+            text.match_indices(&phrase)
+                .map(|(i, m)| i..i + m.len())
+                .collect()
+        }),
+        Query::Regex(regex) => {
+            Box::new(move |text| regex.find_iter(text).map(|m| m.range()).collect())
+        }
+        Query::Or(a, b) => {
+            let (a, b) = (find_spans(*a), find_spans(*b));
+            Box::new(move |text| {
+                let mut spans = a(text);
+                spans.extend(b(text));
+                spans.sort_by_key(|span| (span.start, span.end));
+                spans
+            })
+        }
+        Query::Near(a, b, distance) => {
+            let (a, b) = (find_spans(*a), find_spans(*b));
+            Box::new(move |text| {
+                let mut spans = Vec::new();
+                for x in a(text) {
+                    for y in b(text) {
+                        let (first, second) = if x.start <= y.start {
+                            (&x, &y)
+                        } else {
+                            (&y, &x)
+                        };
+                        if word_distance(text, first, second) <= distance {
+                            spans.push(first.start..first.end.max(second.end));
+                        }
+                    }
+                }
+                spans.sort_by_key(|span| (span.start, span.end));
+                spans.dedup();
+                spans
+            })
+        }
+        // The parser only allows NEAR between queries with positions
+        Query::And(..) | Query::Not(_) => Box::new(|_| Vec::new()),
+    }
+}
+
+/// The number of words from the end of the first span to the start of the
+/// second, so adjacent words are 1 apart
+fn word_distance(text: &str, first: &Range<usize>, second: &Range<usize>) -> usize {
+    if second.start < first.end {
+        return 0;
+    }
+    let last_word = text[..first.end].split_whitespace().count();
+    let next_word = text[..second.start].split_whitespace().count();
+    (next_word + 1).saturating_sub(last_word)
+}
+
+/// Split an argument of the form /regexp/ or /regexp/i into the pattern and
+/// whether to ignore case. Returns None if it is not a regexp.
+pub fn split_regexp(s: &str) -> Option<(&str, bool)> {
+    let s = s.strip_prefix('/')?;
+    match s.strip_suffix("/i") {
+        Some(pattern) => Some((pattern, true)),
+        None => s.strip_suffix('/').map(|pattern| (pattern, false)),
+    }
+}
+
+/// The reference and text of a span matched by NEAR, which may run on into
+/// the next verse
+fn span_text(
+    verse_ref: &VerseRef,
+    verse: &str,
+    next: Option<(VerseRef, &str)>,
+    span: &Range<usize>,
+) -> (String, String) {
+    match next {
+        Some((next_ref, next)) if span.end > verse.len() => {
+            let text = format!("{} {}", verse, next);
+            (
+                format!("{}-{}", verse_ref, next_ref.verse),
+                text[span.clone()].to_string(),
+            )
+        }
+        _ => (verse_ref.to_string(), verse[span.clone()].to_string()),
+    }
+}
+
+/// Collect regexes for the terms of the query that a verse matches on, i.e.
+/// those that are not negated, so they can be highlighted
+fn query_terms(query: &Query, negated: bool, regexes: &mut Vec<Regex>) {
+    match query {
+        Query::Word(word) if !negated => regexes.extend(word_regexp(word)),
+        Query::Phrase(phrase) if !negated => regexes.extend(Regex::new(&regex::escape(phrase))),
+        Query::Regex(regex) if !negated => regexes.push(regex.clone()),
+        Query::And(a, b) | Query::Or(a, b) | Query::Near(a, b, _) => {
+            query_terms(a, negated, regexes);
+            query_terms(b, negated, regexes);
+        }
+        Query::Not(a) => query_terms(a, !negated, regexes),
+        _ => (),
+    }
+}
+
+/// Collect the words in the query to rank the matches by
+fn query_words(query: &Query, negated: bool, terms: &mut Vec<String>) {
+    match query {
+        Query::Word(word) | Query::Phrase(word) if !negated => terms.extend(words(word)),
+        Query::And(a, b) | Query::Or(a, b) | Query::Near(a, b, _) => {
+            query_words(a, negated, terms);
+            query_words(b, negated, terms);
+        }
+        Query::Not(a) => query_words(a, !negated, terms),
+        _ => (),
+    }
+}
+
+/// The verses the index says may match the query
+fn query_candidates(query: &Query, index: &Index, across_verses: bool) -> Candidates {
+    match query {
+        Query::Word(word) => index.word(word),
+        Query::Phrase(phrase) => index.phrase(phrase),
+        Query::And(a, b) => both(
+            query_candidates(a, index, across_verses),
+            query_candidates(b, index, across_verses),
+        ),
+        Query::Or(a, b) => either(
+            query_candidates(a, index, across_verses),
+            query_candidates(b, index, across_verses),
+        ),
+        Query::Near(a, b, _) if !across_verses => both(
+            query_candidates(a, index, across_verses),
+            query_candidates(b, index, across_verses),
+        ),
+        Query::Regex(_) | Query::Not(_) | Query::Near(..) => None,
+    }
+}
+
+pub fn search<T: Read + Seek>(
+    bar: BARFile<T>,
+    archive: &Path,
+    params: &SearchArgs,
+    format: Format,
+) -> i32 {
+    let index = load_index(archive);
+    match search_internal(
+        bar,
+        archive,
+        params,
+        format,
+        params.color.enabled(),
+        index.as_ref(),
+    ) {
+        Err(error) => {
+            eprintln!("Error while performing search");
+            eprintln!("{}", error);
+            1
+        }
+        Ok(_) => 0,
+    }
+}
+
+/// Load the index for the BARFile if there is one, otherwise everything
+/// will be searched
+fn load_index(archive: &Path) -> Option<Index> {
+    match Index::load(archive) {
+        Ok(index) => index,
+        Err(error) => {
+            eprintln!("{} Searching without the index.", error);
+            None
+        }
+    }
 }
 
-pub fn search<T: Read + Seek>(bar: BARFile<T>, params: &SearchArgs) -> i32 {
-    match search_internal(bar, params) {
+/// Search each of the versions, grouping the results by version
+pub fn search_versions<T: Read + Seek>(
+    bars: Vec<(String, PathBuf, BARFile<T>)>,
+    params: &SearchArgs,
+    format: Format,
+) -> i32 {
+    match search_versions_internal(bars, params, format, params.color.enabled()) {
         Err(error) => {
             eprintln!("Error while performing search");
             eprintln!("{}", error);
@@ -82,256 +512,1141 @@ pub fn search<T: Read + Seek>(bar: BARFile<T>, params: &SearchArgs) -> i32 {
     }
 }
 
+fn search_versions_internal<T: Read + Seek>(
+    bars: Vec<(String, PathBuf, BARFile<T>)>,
+    params: &SearchArgs,
+    format: Format,
+    color: bool,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut output: Vec<String> = Vec::new();
+    let mut table: Option<Table> = None;
+    let mut totals: Vec<(String, String)> = Vec::new();
+    for (name, path, bar) in bars {
+        if format.is_text() {
+            if !output.is_empty() {
+                oprintln!(output, "");
+            }
+            oprintln!(output, "== {} ==", name);
+        }
+        let index = load_index(&path);
+        let archive = Archive {
+            version: Some(&name),
+            path: &path,
+            bar,
+            index: index.as_ref(),
+        };
+        let (version_table, total) = search_archive(archive, params, format, color, &mut output)?;
+        match table.as_mut() {
+            Some(table) => table.append(version_table),
+            None => table = Some(version_table),
+        }
+        totals.push((name, total));
+    }
+    // Compare the totals at the end
+    if params.count && format.is_text() && totals.len() > 1 {
+        oprintln!(output, "");
+        oprintln!(output, "Total by version:");
+        for (name, total) in totals.iter() {
+            oprintln!(output, "  {}: {}", name, total);
+        }
+    }
+    if let Some(table) = table {
+        for line in table.render(format) {
+            oprintln!(output, "{}", line);
+        }
+    }
+    Ok(output)
+}
+
+/// A BARFile to search, along with where it is and its index
+struct Archive<'a, T> {
+    /// Name of the version, when searching more than one
+    version: Option<&'a str>,
+    path: &'a Path,
+    bar: BARFile<T>,
+    index: Option<&'a Index>,
+}
+
 fn search_internal<T: Read + Seek>(
     bar: BARFile<T>,
+    archive: &Path,
     params: &SearchArgs,
+    format: Format,
+    color: bool,
+    index: Option<&Index>,
 ) -> Result<Vec<String>, Box<dyn Error>> {
     let mut output: Vec<String> = Vec::new();
-    // Set up the filters required
-    let mut book_filters: Vec<Box<dyn Fn(bool, u32) -> bool>> = Vec::new();
-    let mut chapter_filters: HashMap<u32, Vec<Box<dyn Fn(bool, u32) -> bool>>> = HashMap::new();
-    let mut match_filters: Vec<Box<dyn Fn(&str) -> bool>> = Vec::new();
-    let mut must_match_filters: Vec<Box<dyn Fn(&str) -> bool>> = Vec::new();
-    let mut exclude_filters: Vec<Box<dyn Fn(&str) -> bool>> = Vec::new();
-
-    // Parse the arguments to populate the filters
-    // Path includes for books and chapters
-    for m in params.include.iter() {
-        let is_exclude = m.starts_with("!");
-        let mut s = &m[..];
-        if is_exclude {
-            s = &m[1..];
-        } else if book_filters.is_empty() {
-            // If first filter is an include assume everything is initially excluded
-            book_filters.push(Box::new(exclude_all()));
-        }
-        // Check for range cases
-        if s == "OT" || s == "NT" || (s.contains("..") && !s.contains(" ")) {
-            let range;
-            if s == "OT" {
-                range = 1..=39;
-            } else if s == "NT" {
-                range = 40..=66;
-            } else {
-                let parts: Vec<&str> = s.split("..").collect();
-                if parts.len() != 2 {
-                    return Err(format!("Invalid argument for --include: {}", m).into());
+    let archive = Archive {
+        version: None,
+        path: archive,
+        bar,
+        index,
+    };
+    let (table, _) = search_archive(archive, params, format, color, &mut output)?;
+    for line in table.render(format) {
+        oprintln!(output, "{}", line);
+    }
+    Ok(output)
+}
+
+/// Search the archive, writing out the text output as it goes. Returns the
+/// table of results for the structured formats and the total for --count.
+fn search_archive<T: Read + Seek>(
+    archive: Archive<T>,
+    params: &SearchArgs,
+    format: Format,
+    color: bool,
+    output: &mut Vec<String>,
+) -> Result<(Table, String), Box<dyn Error>> {
+    let Archive {
+        version,
+        path,
+        bar,
+        index,
+    } = archive;
+    let searcher = Searcher::new(params, format, color, index)?;
+    let mut columns = searcher.columns();
+    // Tag the results with the version when searching more than one
+    if version.is_some() {
+        columns.insert(0, "version");
+    }
+    let mut report = Report {
+        version,
+        table: Table::new(&columns),
+        output,
+    };
+    let books: Vec<u8> = bar
+        .books_in_order()
+        .map(|book| book.book_number())
+        .filter(|b| searcher.includes_book(*b))
+        .collect();
+    let threads = params
+        .threads
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+
+    // Write out the results for each book in order
+    let mut has_output = false;
+    let mut totals = BookResults::default();
+    search_books(&bar, path, &searcher, &books, threads, |mut results| {
+        let output = &mut report.output;
+        if searcher.show_context && format.is_text() && has_output && !results.lines.is_empty() {
+            // Context does not run across books
+            oprintln!(output, "--");
+        }
+        has_output |= !results.lines.is_empty();
+        for line in results.lines.drain(..) {
+            oprintln!(output, "{}", line);
+        }
+        for row in results.rows.drain(..) {
+            report.push(row);
+        }
+        totals.merge(results);
+    })?;
+
+    let total = if searcher.tally.is_some() {
+        searcher.report_tally(totals.tallied, &mut report);
+        totals.count.to_string()
+    } else if searcher.rank.is_some() {
+        searcher.report_ranked(&totals.corpus, totals.ranked, params.top, &mut report);
+        totals.count.to_string()
+    } else if params.count {
+        searcher.report_counts(totals, params, &mut report)
+    } else {
+        totals.count.to_string()
+    };
+    Ok((report.table, total))
+}
+
+/// Where the results of searching an archive go. Text is written out as it
+/// goes and rows are added to the table, tagged with the version if any.
+struct Report<'a> {
+    version: Option<&'a str>,
+    table: Table,
+    output: &'a mut Vec<String>,
+}
+
+impl Report<'_> {
+    fn push(&mut self, mut row: Vec<Value>) {
+        if let Some(version) = self.version {
+            row.insert(0, version.into());
+        }
+        self.table.push(row);
+    }
+}
+
+/// Lay out the counts for several words in columns, one row for each group
+fn matrix_lines(terms: &[String], groups: &[Group]) -> Vec<String> {
+    let mut header = vec!["".to_string(), "verses".to_string()];
+    header.extend(terms.iter().map(|term| term.to_string()));
+    let mut rows = vec![header];
+    for group in groups {
+        let mut row = vec![group.label.clone(), group.verses.to_string()];
+        row.extend(group.words.iter().map(|n| n.to_string()));
+        rows.push(row);
+    }
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|i| rows.iter().map(|row| row[i].len()).max().unwrap_or(0))
+        .collect();
+    rows.iter()
+        .map(|row| {
+            let mut line = format!("{:<width$}", row[0], width = widths[0]);
+            for (field, width) in row.iter().zip(widths.iter()).skip(1) {
+                line.push_str(&format!("  {:>width$}", field, width = width));
+            }
+            line.trim_end().to_string()
+        })
+        .collect()
+}
+
+/// Add the number of times each word was found to the totals so far
+fn add_words(totals: &mut Vec<usize>, words: &[usize]) {
+    if totals.len() < words.len() {
+        totals.resize(words.len(), 0);
+    }
+    for (total, n) in totals.iter_mut().zip(words.iter()) {
+        *total += n;
+    }
+}
+
+/// Search the books, in parallel if there is more than one thread, passing
+/// the results for each book to write_results in canonical order
+fn search_books<T: Read + Seek>(
+    bar: &BARFile<T>,
+    archive: &Path,
+    searcher: &Searcher,
+    books: &[u8],
+    threads: usize,
+    mut write_results: impl FnMut(BookResults),
+) -> Result<(), Box<dyn Error>> {
+    if threads <= 1 || books.len() <= 1 {
+        for b in books {
+            write_results(searcher.search_book(bar, *b));
+        }
+        return Ok(());
+    }
+    let next_book = AtomicUsize::new(0);
+    thread::scope(|s| {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..threads.min(books.len()) {
+            let sender = sender.clone();
+            let next_book = &next_book;
+            s.spawn(move || {
+                // Each thread reads and decompresses through its own BARFile
+                let bar = match BARFile::open(archive) {
+                    Ok(bar) => bar,
+                    Err(error) => {
+                        let _ = sender.send(Err(error.to_string()));
+                        return;
+                    }
+                };
+                loop {
+                    let i = next_book.fetch_add(1, Ordering::Relaxed);
+                    if i >= books.len() {
+                        break;
+                    }
+                    if sender
+                        .send(Ok((i, searcher.search_book(&bar, books[i]))))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+        // Hold on to books that finish early until the ones before them
+        // have been written
+        let mut finished: BTreeMap<usize, BookResults> = BTreeMap::new();
+        let mut next_to_write = 0;
+        let mut error: Option<String> = None;
+        for message in receiver {
+            match message {
+                Ok((i, results)) => {
+                    finished.insert(i, results);
                 }
-                let start = parse_book_abbrev(parts[0]);
-                let end = parse_book_abbrev(parts[1]);
-                if start.is_none() || end.is_none() {
-                    return Err(format!("Invalid range for --include: {}", m).into());
+                Err(e) => {
+                    error.get_or_insert(e);
                 }
-                let start = start.unwrap() as u32;
-                let end = end.unwrap() as u32;
-                if end < start {
-                    return Err(format!(
-                        "Invalid range for --include: {}. {} is after {}",
-                        m, parts[0], parts[1]
-                    )
-                    .into());
+            }
+            while let Some(results) = finished.remove(&next_to_write) {
+                write_results(results);
+                next_to_write += 1;
+            }
+        }
+        match error {
+            Some(error) if next_to_write < books.len() => Err(error.into()),
+            _ => Ok(()),
+        }
+    })
+}
+
+/// The output from searching a book, to be written out once the books
+/// before it have been
+#[derive(Default)]
+struct BookResults {
+    lines: Vec<String>,
+    rows: Vec<Vec<Value>>,
+    groups: Vec<Group>,
+    /// The words matched by each wildcard being counted
+    expansions: BTreeMap<(usize, String), Group>,
+    /// How often each text was matched when tallying
+    tallied: BTreeMap<String, usize>,
+    corpus: Corpus,
+    ranked: Vec<Ranked>,
+    count: u32,
+    word_count: Vec<usize>,
+}
+
+impl BookResults {
+    /// Add the counts, tallies and matches to rank from a later book
+    fn merge(&mut self, results: BookResults) {
+        for group in results.groups {
+            // Testaments carry on from one book to the next
+            match self.groups.last_mut() {
+                Some(last) if last.label == group.label => {
+                    last.verses += group.verses;
+                    add_words(&mut last.words, &group.words);
                 }
-                range = (start + 1)..=(end + 1);
+                _ => self.groups.push(group),
             }
-            book_filters.push(match is_exclude {
-                true => Box::new(exclude_item_range(range)),
-                false => Box::new(include_item_range(range)),
-            });
-        } else {
-            // It should be an individual book or a chapter
-            match BibleBookOrChapter::parse(s) {
-                Some(BibleBookOrChapter::Book(book)) => {
-                    book_filters.push(match is_exclude {
-                        true => Box::new(exclude_item(book.book_number())),
-                        false => Box::new(include_item(book.book_number())),
-                    });
+        }
+        for (key, expansion) in results.expansions {
+            match self.expansions.get_mut(&key) {
+                Some(total) => {
+                    total.verses += expansion.verses;
+                    add_words(&mut total.words, &expansion.words);
                 }
-                Some(BibleBookOrChapter::Chapter(chapt)) => {
-                    let book = chapt.book.book_number();
-                    let chapter = chapt.chapter as u32;
-                    let is_book_included = book_filters.iter().fold(true, |acc, f| f(acc, book));
-                    if !is_book_included && !is_exclude {
-                        // We want to include a chapter from a book that is currently excluded
-                        // First we need to include the book
-                        book_filters.push(Box::new(include_item(book)));
-                    } else if !is_book_included {
-                        // No need to exclude chapter from book that is already excluded
-                        continue;
-                    }
-                    let mut filters = chapter_filters.get_mut(&book);
-                    if filters.is_none() {
-                        let mut new_filters: Vec<Box<dyn Fn(bool, u32) -> bool>> = Vec::new();
-                        if !is_exclude {
-                            // If first filter is an include assume everything is initially excluded
-                            new_filters.push(Box::new(exclude_all()));
-                        }
-                        chapter_filters.insert(book, new_filters);
-                        filters = chapter_filters.get_mut(&book);
-                    }
-                    let filters = &mut filters.unwrap();
-                    filters.push(match is_exclude {
-                        true => Box::new(exclude_item(chapter)),
-                        false => Box::new(include_item(chapter)),
-                    });
+                None => {
+                    self.expansions.insert(key, expansion);
                 }
-                _ => return Err(format!("Invalid value for --include: {}.", m).into()),
             }
         }
+        for (text, n) in results.tallied {
+            *self.tallied.entry(text).or_default() += n;
+        }
+        self.corpus.merge(results.corpus);
+        self.ranked.extend(results.ranked);
+        self.count += results.count;
+        add_words(&mut self.word_count, &results.word_count);
     }
+}
+
+/// A verse or chapter that matched, to be scored once the whole corpus has
+/// been searched
+struct Ranked {
+    verse_ref: VerseRef,
+    /// The text of the verse, or None when ranking chapters
+    text: Option<String>,
+    counts: TermCounts,
+}
+
+impl Ranked {
+    fn chapter(&self) -> String {
+        format!(
+            "{} {}",
+            self.verse_ref.book_abbrev(),
+            self.verse_ref.chapter
+        )
+    }
+}
+
+/// The matches counted in a verse, chapter, book or testament
+struct Group {
+    label: String,
+    verses: u32,
+    /// How many times each of the words being counted was found
+    words: Vec<usize>,
+}
+
+impl Group {
+    /// The number of words found, counted like the verses so that both
+    /// can be held to the same thresholds
+    fn total_words(&self) -> u32 {
+        self.words.iter().sum::<usize>() as u32
+    }
+}
 
-    // Match and exclude filters for verses
-    for m in params.matching.iter() {
-        let m = m.to_string();
-        let is_exclude = m.starts_with("!");
-        let is_required = m.starts_with("+");
-        let mut s = &m[..];
-        if is_exclude || is_required {
-            s = &m[1..];
+/// The regexps whose matches are tallied, and the capture group to tally
+/// instead of the whole match
+struct Tally {
+    regexes: Vec<Regex>,
+    capture: Option<String>,
+}
+
+impl Tally {
+    fn new(regexes: Vec<Regex>, capture: Option<String>) -> Result<Tally, Box<dyn Error>> {
+        if regexes.is_empty() {
+            return Err("--tally needs a /regexp/ to tally the matches of".into());
         }
-        // Test for regexp
-        let filter: Box<dyn Fn(&str) -> bool>;
-        if s.starts_with("/") && (s.ends_with("/") || s.ends_with("/i")) {
-            let mut ignore_case = false;
-            if s.ends_with("i") {
-                ignore_case = true;
-                s = &s[..s.len() - 1];
+        if let Some(capture) = &capture {
+            for regex in regexes.iter() {
+                let exists = match capture.parse::<usize>() {
+                    Ok(i) => i < regex.captures_len(),
+                    Err(_) => regex.capture_names().flatten().any(|name| name == capture),
+                };
+                if !exists {
+                    return Err(
+                        format!("No capture group {} in /{}/", capture, regex.as_str()).into(),
+                    );
+                }
             }
-            s = &s[1..s.len() - 1];
-            let regex = RegexBuilder::new(s).case_insensitive(ignore_case).build();
-            if regex.is_err() {
-                return Err(format!("Invalid regexp in arg for --include: {}", s).into());
+        }
+        Ok(Tally { regexes, capture })
+    }
+
+    /// Count the texts matched in a verse
+    fn add(&self, tallied: &mut BTreeMap<String, usize>, verse: &str) {
+        for regex in self.regexes.iter() {
+            for captures in regex.captures_iter(verse) {
+                let m = match &self.capture {
+                    None => captures.get(0),
+                    Some(capture) => match capture.parse::<usize>() {
+                        Ok(i) => captures.get(i),
+                        Err(_) => captures.name(capture),
+                    },
+                };
+                // Optional groups may not have taken part in the match
+                if let Some(m) = m {
+                    *tallied.entry(m.as_str().to_string()).or_default() += 1;
+                }
             }
-            filter = Box::new(match_regex(regex.unwrap()));
-        } else {
-            filter = Box::new(match_phrase(s.to_string()));
         }
-        if is_exclude {
-            exclude_filters.push(filter);
-        } else if is_required {
-            must_match_filters.push(filter);
+    }
+}
+
+/// The filters and settings for a search, shared between the threads
+/// searching each book
+struct Searcher {
+    scope: Scope,
+    match_filters: Vec<Filter>,
+    must_match_filters: Vec<Filter>,
+    exclude_filters: Vec<Filter>,
+    query_filter: Option<QueryFilter>,
+    highlighter: Highlighter,
+    candidates: Candidates,
+    candidate_chapters: Option<HashSet<(u8, u8)>>,
+    word_matchers: Vec<Regex>,
+    tally: Option<Tally>,
+    /// The words being counted
+    terms: Vec<String>,
+    format: Format,
+    count: bool,
+    group_by: GroupBy,
+    rank: Option<RankBy>,
+    rank_terms: Vec<String>,
+    stem: bool,
+    spellings: Option<Spellings>,
+    ignore_diacritics: bool,
+    show_spans: bool,
+    show_context: bool,
+    before: usize,
+    after: usize,
+}
+
+impl Searcher {
+    /// Set up the filters and settings for the search from the arguments
+    fn new(
+        params: &SearchArgs,
+        format: Format,
+        color: bool,
+        index: Option<&Index>,
+    ) -> Result<Searcher, Box<dyn Error>> {
+        // Set up the filters required
+        let scope = Scope::parse(&params.include)?;
+        let spellings = if params.spelling || params.spelling_file.is_some() {
+            let mut spellings = Spellings::builtin();
+            if let Some(path) = &params.spelling_file {
+                spellings.load(path)?;
+            }
+            Some(spellings)
         } else {
-            match_filters.push(filter);
+            None
+        };
+        let matching = WordMatching {
+            stem: params.stem,
+            spellings: spellings.as_ref(),
+            ignore_diacritics: params.ignore_diacritics,
+            normalize_phrases: params.normalize_phrases,
+        };
+        let mut match_filters: Vec<Filter> = Vec::new();
+        let mut must_match_filters: Vec<Filter> = Vec::new();
+        let mut exclude_filters: Vec<Filter> = Vec::new();
+        // What to highlight in the verses that match
+        let mut highlights: Vec<Regex> = Vec::new();
+        // The regexps to tally the matches of
+        let mut tally_regexes: Vec<Regex> = Vec::new();
+        // The words to rank the verses that match by
+        let mut rank_terms: Vec<String> = Vec::new();
+        // Verses the index says may match any or all of the filters
+        let mut any_of: Vec<Candidates> = Vec::new();
+        let mut all_of: Vec<Candidates> = Vec::new();
+
+        // Match and exclude filters for verses
+        for m in params.matching.iter() {
+            let m = m.to_string();
+            let is_exclude = m.starts_with("!");
+            let is_required = m.starts_with("+");
+            let mut s = &m[..];
+            if is_exclude || is_required {
+                s = &m[1..];
+            }
+            // Test for regexp
+            let filter: Filter;
+            let candidates: Candidates;
+            if let Some((pattern, ignore_case)) = split_regexp(s) {
+                s = pattern;
+                let pattern = match params.ignore_diacritics {
+                    true => fold_pattern(s),
+                    false => s.to_string(),
+                };
+                let regex = RegexBuilder::new(&pattern)
+                    .case_insensitive(ignore_case)
+                    .build();
+                if regex.is_err() {
+                    return Err(format!("Invalid regexp in arg for --include: {}", s).into());
+                }
+                let regex = regex.unwrap();
+                if !is_exclude {
+                    highlights.push(regex.clone());
+                    tally_regexes.push(regex.clone());
+                }
+                filter = Box::new(match_regex(regex));
+                candidates = None;
+            } else if let Some(regex) = matching.phrase_pattern(s) {
+                // Other spellings of the words in the phrase, or any case and
+                // punctuation
+                if !is_exclude {
+                    highlights.push(regex.clone());
+                    rank_terms.extend(words(s));
+                }
+                filter = Box::new(match_regex(regex));
+                // The index only has the words of the phrase, in lower case.
+                // When normalizing, any punctuation or spaces at either end of
+                // the phrase are ignored, so only look up its words.
+                let phrase = match params.normalize_phrases {
+                    true => words(s).collect::<Vec<_>>().join(" "),
+                    false => s.to_string(),
+                };
+                candidates = match spellings.is_none() && !params.ignore_diacritics {
+                    true => index.and_then(|index| index.phrase(&phrase)),
+                    false => None,
+                };
+            } else {
+                if !is_exclude {
+                    highlights.extend(Regex::new(&regex::escape(s)));
+                    rank_terms.extend(words(s));
+                }
+                filter = Box::new(match_phrase(s.to_string()));
+                candidates = index.and_then(|index| index.phrase(s));
+            }
+            if is_exclude {
+                exclude_filters.push(filter);
+            } else if is_required {
+                must_match_filters.push(filter);
+                all_of.push(candidates);
+            } else {
+                match_filters.push(filter);
+                any_of.push(candidates);
+            }
         }
-    }
 
-    // Same again for words
-    for m in params.word.iter() {
-        let is_exclude = m.starts_with("!");
-        let is_required = m.starts_with("+");
-        let mut s = &m[..];
-        if is_exclude || is_required {
-            s = &m[1..];
+        // Same again for words
+        for m in params.word.iter() {
+            let is_exclude = m.starts_with("!");
+            let is_required = m.starts_with("+");
+            let mut s = &m[..];
+            if is_exclude || is_required {
+                s = &m[1..];
+            }
+            let regex = matching.pattern(s);
+            let filter: Filter = match regex.clone() {
+                Some(regex) => Box::new(match_regex(regex)),
+                None => Box::new(|_| false),
+            };
+            if !is_exclude {
+                highlights.extend(regex);
+                rank_terms.extend(words(s));
+            }
+            let candidates = index.and_then(|index| matching.candidates(index, s));
+            if is_exclude {
+                exclude_filters.push(filter);
+            } else if is_required {
+                must_match_filters.push(filter);
+                all_of.push(candidates);
+            } else {
+                match_filters.push(filter);
+                any_of.push(candidates);
+            }
         }
-        let filter = match_word(s);
-        if is_exclude {
-            exclude_filters.push(filter);
-        } else if is_required {
-            must_match_filters.push(filter);
+
+        // The query must match as well as any other filters
+        let mut query_filter: Option<QueryFilter> = None;
+        let mut show_spans = false;
+        if let Some(q) = &params.query {
+            match Query::parse(q) {
+                Ok(query) => {
+                    // The words are still needed to rank the matches by
+                    query_words(&query, false, &mut rank_terms);
+                    let query = if matching.is_exact() {
+                        query
+                    } else {
+                        matching.expand(query)
+                    };
+                    show_spans = query.has_near();
+                    query_terms(&query, false, &mut highlights);
+                    all_of.push(
+                        index.and_then(|index| {
+                            query_candidates(&query, index, params.across_verses)
+                        }),
+                    );
+                    query_filter = Some(match_query(query, params.across_verses));
+                }
+                Err(error) => {
+                    return Err(format!("Invalid value for --query: {}. {}", q, error).into());
+                }
+            }
+        }
+
+        let markers = if params.brackets && (format.is_text() || format == Format::Markdown) {
+            Some(Markers::Brackets)
+        } else if color && format.is_text() {
+            Some(Markers::Ansi)
         } else {
-            match_filters.push(filter);
+            None
+        };
+        let highlighter = Highlighter::new(highlights, markers);
+
+        // We should keep word counts (not just a verse count) for each word when
+        // only words are being matched. Several words are counted in a matrix.
+        let terms: Vec<&str> =
+            if params.count && params.matching.is_empty() && params.query.is_none() {
+                params
+                    .word
+                    .iter()
+                    .filter(|word| !word.starts_with("!"))
+                    .map(|word| word.strip_prefix("+").unwrap_or(word))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+        let mut word_matchers: Vec<Regex> = Vec::new();
+        for term in terms.iter() {
+            match matching.pattern(term) {
+                Some(regex) => word_matchers.push(regex),
+                None => return Err(format!("Invalid word: {}", term).into()),
+            }
+        }
+        if params.min_words.is_some() && terms.is_empty() {
+            return Err("--min-words needs --word to count and no other matches".into());
         }
+        // Each word only needs to be scored once
+        if let Some(spellings) = &spellings {
+            rank_terms = rank_terms
+                .iter()
+                .map(|term| spellings.modern(term))
+                .collect();
+        }
+        if params.ignore_diacritics {
+            rank_terms = rank_terms.iter().map(|term| fold(term)).collect();
+        }
+        if params.stem {
+            rank_terms = rank_terms.iter().map(|term| stem(term)).collect();
+        }
+        let mut seen: HashSet<String> = HashSet::new();
+        rank_terms.retain(|term| seen.insert(term.clone()));
+        if params.rank && rank_terms.is_empty() {
+            return Err("--rank needs words or phrases to score the matches by".into());
+        }
+        let rank = params.rank.then_some(params.rank_by);
+        let tally = match params.tally {
+            true => Some(Tally::new(tally_regexes, params.capture.clone())?),
+            false => None,
+        };
+        // Context verses to show around each match
+        let before = params.before_context.max(params.context);
+        let after = params.after_context.max(params.context);
+        let show_context = !params.count && (before > 0 || after > 0);
+        // Only the chapters with verses that may match need to be looked at,
+        // except when showing the verses around each match or ranking them
+        // against all the verses
+        if let Some(candidates) = any_of.into_iter().reduce(either) {
+            all_of.push(candidates);
+        }
+        let candidates = match show_context || rank.is_some() {
+            true => None,
+            false => all_of.into_iter().fold(None, both),
+        };
+        let candidate_chapters: Option<HashSet<(u8, u8)>> = candidates.as_ref().map(|candidates| {
+            candidates
+                .iter()
+                .map(|verse_ref| (verse_ref.book, verse_ref.chapter))
+                .collect()
+        });
+        Ok(Searcher {
+            scope,
+            match_filters,
+            must_match_filters,
+            exclude_filters,
+            query_filter,
+            highlighter,
+            candidates,
+            candidate_chapters,
+            word_matchers,
+            tally,
+            terms: terms.iter().map(|term| term.to_string()).collect(),
+            format,
+            count: params.count,
+            group_by: params.group_by,
+            rank,
+            rank_terms,
+            stem: params.stem,
+            spellings,
+            ignore_diacritics: params.ignore_diacritics,
+            show_spans,
+            show_context,
+            before,
+            after,
+        })
     }
 
-    // Process the books, chapters and verses and find the matches
-    // using the created filters
-    let mut count = 0;
-    let mut word_count = 0;
-    // We should keep a wrod count (not just a verse count) if there is a single match to count
-    let should_word_count = params.count && params.word.len() == 1 && params.matching.len() == 0;
-    let word_matcher: Option<Regex> = if should_word_count {
-        let word = &params.word[0];
-        word_regexp(word)
-    } else {
-        None
-    };
-    for book in bar.books_in_order() {
-        let b = book.book_number() as u32;
-        let should_proccess = book_filters.iter().fold(true, |acc, f| f(acc, b));
-        if !should_proccess {
-            continue;
+    /// The columns of the table of results for the structured formats
+    fn columns(&self) -> Vec<&str> {
+        if self.tally.is_some() {
+            vec!["match", "count"]
+        } else if self.rank == Some(RankBy::Chapter) {
+            vec!["reference", "score"]
+        } else if self.rank.is_some() {
+            let mut columns = VERSE_COLUMNS.to_vec();
+            columns.push("score");
+            columns
+        } else if self.show_context {
+            let mut columns = VERSE_COLUMNS.to_vec();
+            columns.push("hit");
+            columns
+        } else if !self.count && self.show_spans {
+            let mut columns = VERSE_COLUMNS.to_vec();
+            columns.push("spans");
+            columns
+        } else if !self.count {
+            VERSE_COLUMNS.to_vec()
+        } else if self.terms.len() == 1 {
+            vec!["reference", "verses", "words"]
+        } else if self.terms.len() > 1 {
+            let mut columns = vec!["reference", "verses"];
+            columns.extend(self.terms.iter().map(|term| &term[..]));
+            columns
+        } else {
+            vec!["reference", "verses"]
         }
-        let book_chapt_filters = &chapter_filters.get_mut(&b);
+    }
+
+    fn includes_book(&self, b: u8) -> bool {
+        self.scope.includes_book(b)
+            && self
+                .candidate_chapters
+                .as_ref()
+                .is_none_or(|chapters| chapters.iter().any(|(book, _)| *book == b))
+    }
+
+    fn search_book<T: Read + Seek>(&self, bar: &BARFile<T>, b: u8) -> BookResults {
+        let mut results = BookResults::default();
+        let book = match bar.book(b) {
+            Some(book) => book,
+            None => return results,
+        };
+        let format = self.format;
+        let highlighter = &self.highlighter;
+        let mut context = Context::new(self.before, self.after);
         for chapter in book.chapters() {
             if chapter.is_none() {
+                context.reset();
                 continue;
             }
-            let mut chapter_count = 0;
-            let mut chapter_word_count = 0;
             let chapter = chapter.unwrap();
-            let c = chapter.chapter_number() as u32;
-            if let Some(filters) = book_chapt_filters {
-                let should_proccess = filters.iter().fold(true, |acc, f| f(acc, c));
-                if !should_proccess {
+            let c = chapter.chapter_number();
+            if !self.scope.includes_chapter(b, c) {
+                context.reset();
+                continue;
+            }
+            if self
+                .candidate_chapters
+                .as_ref()
+                .is_some_and(|chapters| !chapters.contains(&(b, c)))
+            {
+                continue;
+            }
+            let verses: Vec<(u8, String)> = chapter.enumerated_verses().collect();
+            // Ranking chapters scores the whole of a chapter with a match
+            let mut chapter_counts = TermCounts::new(&self.rank_terms);
+            let mut chapter_match: Option<VerseRef> = None;
+            for (i, (v, verse)) in verses.iter().enumerate() {
+                let verse_ref = VerseRef::new(b, c, *v);
+                let next_verse = verses
+                    .get(i + 1)
+                    .map(|(v, text)| (VerseRef::new(b, c, *v), &text[..]));
+                let next = next_verse.map(|(_, text)| text);
+                let is_included = self.scope.includes_verse(&verse_ref)
+                    && self
+                        .candidates
+                        .as_ref()
+                        .is_none_or(|candidates| candidates.contains(&verse_ref));
+                let spans = match &self.query_filter {
+                    Some(f) if is_included => f(verse, next),
+                    _ => Some(Vec::new()),
+                };
+                let should_process = is_included
+                    && spans.is_some()
+                    && (self.match_filters.is_empty()
+                        || self.match_filters.iter().any(|f| f(verse)))
+                    && self.must_match_filters.iter().all(|f| f(verse))
+                    && !self.exclude_filters.iter().any(|f| f(verse));
+                if self.rank == Some(RankBy::Chapter) && is_included {
+                    chapter_counts.add(&self.rank_terms, self.rank_words(verse));
+                    if should_process {
+                        chapter_match.get_or_insert(verse_ref);
+                    }
+                } else if self.rank.is_some() && is_included {
+                    let mut counts = TermCounts::new(&self.rank_terms);
+                    counts.add(&self.rank_terms, self.rank_words(verse));
+                    results.corpus.add(&counts);
+                    if should_process {
+                        results.ranked.push(Ranked {
+                            verse_ref,
+                            text: Some(verse.clone()),
+                            counts,
+                        });
+                    }
+                }
+                if self.rank.is_some() {
+                    results.count += should_process as u32;
                     continue;
                 }
-            }
-            for (v, verse) in chapter.enumerated_verses() {
-                let should_process = (match_filters.is_empty()
-                    || match_filters.iter().any(|f| f(&verse)))
-                    && must_match_filters.iter().all(|f| f(&verse))
-                    && !exclude_filters.iter().any(|f| f(&verse));
+                if self.show_context && !is_included {
+                    context.reset();
+                } else if self.show_context {
+                    for mut line in context.push(verse_ref, verse.clone(), should_process) {
+                        if line.is_hit {
+                            line.text = highlighter.highlight(&line.text);
+                        }
+                        if format.is_text() {
+                            if line.is_new_group {
+                                results.lines.push("--".to_string());
+                            }
+                            results.lines.push(line.to_string());
+                        } else {
+                            let mut row = verse_row(&line.verse_ref, &line.text);
+                            row.push(line.is_hit.into());
+                            results.rows.push(row);
+                        }
+                    }
+                }
                 if !should_process {
                     continue;
                 }
-                if !params.count {
-                    oprintln!(
-                        output,
-                        "{} {}:{} {}",
-                        BOOK_ABBREVS[b as usize - 1],
-                        c,
-                        v,
-                        verse
-                    );
+                if self.show_context {
+                    // Already output along with its context
+                } else if let Some(tally) = &self.tally {
+                    tally.add(&mut results.tallied, verse);
+                } else if !self.count && format.is_text() {
+                    results
+                        .lines
+                        .push(format!("{} {}", verse_ref, highlighter.highlight(verse)));
+                    for span in spans.iter().flatten() {
+                        let (span_ref, text) = span_text(&verse_ref, verse, next_verse, span);
+                        results.lines.push(format!("  span {}: {}", span_ref, text));
+                    }
+                } else if !self.count {
+                    let mut row = verse_row(&verse_ref, &highlighter.highlight(verse));
+                    if self.show_spans {
+                        let spans: Vec<String> = spans
+                            .iter()
+                            .flatten()
+                            .map(|span| {
+                                let (span_ref, text) =
+                                    span_text(&verse_ref, verse, next_verse, span);
+                                format!("{}: {}", span_ref, text)
+                            })
+                            .collect();
+                        row.push(spans.join("; ").into());
+                    }
+                    results.rows.push(row);
                 }
-                chapter_count += 1;
-                count += 1;
-                if should_word_count && word_matcher.is_some() {
-                    let wc = word_matcher.as_ref().unwrap().find_iter(&verse).count();
-                    chapter_word_count += wc;
-                    word_count += wc;
+                let words: Vec<usize> = self
+                    .word_matchers
+                    .iter()
+                    .map(|regex| regex.find_iter(verse).count())
+                    .collect();
+                results.count += 1;
+                add_words(&mut results.word_count, &words);
+                if self.count {
+                    self.add_to_group(&mut results.groups, &verse_ref, words);
+                    self.add_expansions(&mut results.expansions, verse);
                 }
             }
-            if params.count && chapter_count > 0 {
-                // Display count if count is above threshold
-                if params.threshold.is_none()
-                    || chapter_count >= params.threshold.unwrap()
-                    || chapter_word_count as u32 >= params.threshold.unwrap()
-                {
-                    let extra = if should_word_count {
-                        format!(" (word count: {})", chapter_word_count)
-                    } else {
-                        "".to_string()
-                    };
-                    oprintln!(
-                        output,
-                        "{} {}: {}{}",
-                        BOOK_ABBREVS[b as usize - 1],
-                        c,
-                        chapter_count,
-                        extra
-                    );
+            if !chapter_counts.is_empty() {
+                results.corpus.add(&chapter_counts);
+                if let Some(verse_ref) = chapter_match {
+                    results.ranked.push(Ranked {
+                        verse_ref,
+                        text: None,
+                        counts: chapter_counts,
+                    });
                 }
             }
         }
+        results
+    }
+
+    /// The words of a verse to rank it by, with the modern spelling, without
+    /// diacritics and stemmed if the search is
+    fn rank_words(&self, text: &str) -> Vec<String> {
+        words(text)
+            .map(|word| match self.ignore_diacritics {
+                true => fold(&word),
+                false => word,
+            })
+            .map(|word| match &self.spellings {
+                Some(spellings) => spellings.modern(&word),
+                None => word,
+            })
+            .map(|word| match self.stem {
+                true => stem(&word),
+                false => word,
+            })
+            .collect()
+    }
+
+    /// Count the words in a matching verse that each wildcard matched
+    fn add_expansions(&self, expansions: &mut BTreeMap<(usize, String), Group>, verse: &str) {
+        for (i, term) in self.terms.iter().enumerate() {
+            if !is_wildcard(term) {
+                continue;
+            }
+            // Words differing only in case are the same word when the
+            // wildcard ignores case
+            let ignore_case = is_lower_case(term);
+            let mut found: BTreeMap<String, usize> = BTreeMap::new();
+            for m in self.word_matchers[i].find_iter(verse) {
+                let word = match ignore_case {
+                    true => m.as_str().to_lowercase(),
+                    false => m.as_str().to_string(),
+                };
+                *found.entry(word).or_default() += 1;
+            }
+            for (word, n) in found {
+                let expansion = expansions
+                    .entry((i, word.clone()))
+                    .or_insert_with(|| Group {
+                        label: word,
+                        verses: 0,
+                        words: vec![0; self.terms.len()],
+                    });
+                expansion.verses += 1;
+                expansion.words[i] += n;
+            }
+        }
+    }
+
+    /// Count a matching verse in the group it belongs to
+    fn add_to_group(&self, groups: &mut Vec<Group>, verse_ref: &VerseRef, words: Vec<usize>) {
+        let label = match self.group_by {
+            GroupBy::Verse => verse_ref.to_string(),
+            GroupBy::Chapter => format!("{} {}", verse_ref.book_abbrev(), verse_ref.chapter),
+            GroupBy::Book => verse_ref.book_abbrev().to_string(),
+            GroupBy::Testament => testament(verse_ref.book).to_string(),
+            GroupBy::None => return,
+        };
+        match groups.last_mut() {
+            Some(last) if last.label == label => {
+                last.verses += 1;
+                add_words(&mut last.words, &words);
+            }
+            _ => groups.push(Group {
+                label,
+                verses: 1,
+                words,
+            }),
+        }
+    }
+
+    /// Write out each text tallied and how often it was found, most often
+    /// found first, otherwise in alphabetical order
+    fn report_tally(&self, tallied: BTreeMap<String, usize>, report: &mut Report) {
+        let mut tallied: Vec<(String, usize)> = tallied.into_iter().collect();
+        tallied.sort_by_key(|(_, n)| Reverse(*n));
+        for (text, n) in tallied {
+            if self.format.is_text() {
+                let output = &mut report.output;
+                oprintln!(output, "{}: {}", text, n);
+                continue;
+            }
+            report.push(vec![text.into(), n.into()]);
+        }
     }
-    if params.count {
+
+    /// Write out the top verses or chapters, best scoring first
+    fn report_ranked(&self, corpus: &Corpus, ranked: Vec<Ranked>, top: usize, report: &mut Report) {
+        // Stable, so equal scores stay in order
+        let mut scored: Vec<(f64, Ranked)> = ranked
+            .into_iter()
+            .map(|ranked| (corpus.score(&ranked.counts), ranked))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.truncate(top);
+        for (score, ranked) in scored {
+            let output = &mut report.output;
+            let score = (score * 1000.0).round() / 1000.0;
+            let mut row = match &ranked.text {
+                Some(text) if self.format.is_text() => {
+                    let text = self.highlighter.highlight(text);
+                    oprintln!(output, "{:.3} {} {}", score, ranked.verse_ref, text);
+                    continue;
+                }
+                None if self.format.is_text() => {
+                    oprintln!(output, "{:.3} {}", score, ranked.chapter());
+                    continue;
+                }
+                Some(text) => verse_row(&ranked.verse_ref, &self.highlighter.highlight(text)),
+                None => vec![ranked.chapter().into()],
+            };
+            row.push(score.into());
+            report.push(row);
+        }
+    }
+
+    /// Write out the counts that meet the thresholds, the total, then the
+    /// words each wildcard matched. Returns the total.
+    fn report_counts(
+        &self,
+        totals: BookResults,
+        params: &SearchArgs,
+        report: &mut Report,
+    ) -> String {
+        let BookResults {
+            mut groups,
+            expansions,
+            count,
+            mut word_count,
+            ..
+        } = totals;
+        let terms = &self.terms;
+        let should_word_count = terms.len() == 1;
+        let is_matrix = terms.len() > 1;
+        groups.retain(|group| {
+            params.threshold.is_none_or(|threshold| {
+                group.verses >= threshold || group.total_words() >= threshold
+            }) && params.min_verses.is_none_or(|min| group.verses >= min)
+                && params
+                    .min_words
+                    .is_none_or(|min| group.total_words() >= min)
+        });
+        if params.sort == CountSort::Count {
+            // Stable, so equal counts stay in order
+            groups.sort_by_key(|group| Reverse((group.total_words(), group.verses)));
+        }
+        word_count.resize(terms.len(), 0);
+        groups.push(Group {
+            label: "Total".to_string(),
+            verses: count,
+            words: word_count.clone(),
+        });
+        // Then the words each wildcard matched, most often found first
+        let mut expansions: Vec<Group> = expansions.into_values().collect();
+        expansions.sort_by_key(|expansion| Reverse(expansion.total_words()));
+        for mut expansion in expansions {
+            if self.format.is_text() {
+                expansion.label = format!("  {}", expansion.label);
+            }
+            expansion.words.resize(terms.len(), 0);
+            groups.push(expansion);
+        }
+        if !self.format.is_text() {
+            for group in groups {
+                let mut row = vec![group.label.into(), group.verses.into()];
+                row.extend(group.words.into_iter().map(Value::from));
+                report.push(row);
+            }
+        } else if is_matrix {
+            let output = &mut report.output;
+            for line in matrix_lines(terms, &groups) {
+                oprintln!(output, "{}", line);
+            }
+        } else {
+            let output = &mut report.output;
+            for group in groups {
+                let extra = if should_word_count {
+                    format!(" (word count: {})", group.words[0])
+                } else {
+                    "".to_string()
+                };
+                oprintln!(output, "{}: {}{}", group.label, group.verses, extra);
+            }
+        }
+
         let extra = if should_word_count {
-            format!(" (word count: {})", word_count)
+            format!(" (word count: {})", word_count[0])
+        } else if is_matrix {
+            let counts: Vec<String> = terms
+                .iter()
+                .zip(word_count.iter())
+                .map(|(term, n)| format!("{}: {}", term, n))
+                .collect();
+            format!(" ({})", counts.join(", "))
         } else {
             "".to_string()
         };
-        oprintln!(output, "Total: {}{}", count, extra);
+        format!("{}{}", count, extra)
     }
-    Ok(output)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs::File;
+    use std::sync::LazyLock;
+
+    const KJV: &str = "tests/data/KJV.ibar";
 
     fn barfile() -> BARFile<File> {
-        BARFile::open("tests/data/KJV.ibar").unwrap()
+        BARFile::open(KJV).unwrap()
+    }
+
+    /// Search the archive as text without color or the index
+    fn search_internal(
+        bar: BARFile<File>,
+        params: &SearchArgs,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        super::search_internal(bar, Path::new(KJV), params, Format::Text, false, None)
+    }
+
+    /// Search the test archive in the format given, with or without the index
+    fn search_with(
+        params: &SearchArgs,
+        format: Format,
+        index: Option<&Index>,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        super::search_internal(barfile(), Path::new(KJV), params, format, false, index)
+    }
+
+    /// The index of the test archive, built once for all the tests
+    static INDEX: LazyLock<Index> = LazyLock::new(|| Index::build(&barfile()));
+
+    /// Arguments to count the matches in the books given as a single total
+    fn count_args(include: &[&str]) -> SearchArgs {
+        SearchArgs {
+            include: include.iter().map(|s| s.to_string()).collect(),
+            count: true,
+            group_by: GroupBy::None,
+            ..Default::default()
+        }
+    }
+
+    /// Search using the index, checking that searching every verse finds
+    /// the same
+    fn count(params: &SearchArgs, format: Format) -> Vec<String> {
+        let output = search_with(params, format, Some(&INDEX)).unwrap();
+        assert_eq!(output, search_with(params, format, None).unwrap());
+        output
     }
 
     #[test]
@@ -355,6 +1670,7 @@ mod tests {
             include: vec!["Ps 119".to_string()],
             count: false,
             threshold: None,
+            ..Default::default()
         };
         let output = search_internal(barfile(), &params).unwrap();
         assert_eq!(
@@ -375,6 +1691,7 @@ mod tests {
             include: vec!["Ps 119".to_string()],
             count: false,
             threshold: None,
+            ..Default::default()
         };
         let output = search_internal(barfile(), &params).unwrap();
         assert_eq!(
@@ -396,6 +1713,7 @@ mod tests {
             include: vec!["NT".to_string()],
             count: true,
             threshold: Some(7),
+            ..Default::default()
         };
         let output = search_internal(barfile(), &params).unwrap();
         assert_eq!(
@@ -417,6 +1735,7 @@ mod tests {
             include: vec!["Mt..Jn".to_string(), "1Pe..2Pe".to_string()],
             count: false,
             threshold: None,
+            ..Default::default()
         };
         let output = search_internal(barfile(), &params).unwrap();
         assert_eq!(
@@ -427,4 +1746,523 @@ mod tests {
             ]
         )
     }
+
+    #[test]
+    fn test_query_grace_not_law() {
+        let params = SearchArgs {
+            query: Some("(grace OR mercy) AND NOT law".to_string()),
+            include: vec!["Ga".to_string()],
+            count: true,
+            ..Default::default()
+        };
+        let output = search_internal(barfile(), &params).unwrap();
+        assert_eq!(output, vec!["Ga 1: 3", "Ga 2: 1", "Ga 6: 2", "Total: 6"])
+    }
+
+    #[test]
+    fn test_query_near() {
+        let mut params = SearchArgs {
+            query: Some("faith NEAR/3 works".to_string()),
+            include: vec!["Jas 2:17-20".to_string()],
+            ..Default::default()
+        };
+        let output = search_internal(barfile(), &params).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "Jas 2:18 Yea, a man may say, Thou hast faith, and I have works: shew me thy faith without thy works, and I will shew thee my faith by my works.",
+                "  span Jas 2:18: faith without thy works",
+                "  span Jas 2:18: faith by my works",
+                "Jas 2:20 But wilt thou know, O vain man, that faith without works is dead?",
+                "  span Jas 2:20: faith without works",
+            ]
+        );
+
+        params.query = Some("want NEAR/3 maketh".to_string());
+        params.include = vec!["Ps 23".to_string()];
+        let output = search_internal(barfile(), &params).unwrap();
+        assert!(output.is_empty());
+        params.across_verses = true;
+        let output = search_internal(barfile(), &params).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "Ps 23:1 The Lord is my shepherd; I shall not want.",
+                "  span Ps 23:1-2: want. He maketh",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_span_text_across_missing_verse() {
+        // Versions that leave out a verse go straight on to the one after
+        let verse = "Then saith he to the disciples,";
+        let next = (VerseRef::new(40, 18, 12), "How think ye?");
+        let span = 21..37;
+        assert_eq!(
+            span_text(&VerseRef::new(40, 18, 10), verse, Some(next), &span),
+            ("Mt 18:10-12".to_string(), "disciples, How t".to_string())
+        );
+        assert_eq!(
+            span_text(&VerseRef::new(40, 18, 10), verse, Some(next), &(0..4)),
+            ("Mt 18:10".to_string(), "Then".to_string())
+        );
+    }
+
+    #[test]
+    fn test_highlight_brackets() {
+        let params = SearchArgs {
+            matching: vec!["/green \\w+/".to_string(), "!sheep".to_string()],
+            query: Some("maketh OR NOT leadeth".to_string()),
+            include: vec!["Ps 23:1-2".to_string()],
+            brackets: true,
+            ..Default::default()
+        };
+        // Color is on but brackets take its place
+        let output = super::search_internal(
+            barfile(),
+            Path::new(KJV),
+            &params,
+            Format::Markdown,
+            true,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "| reference | book | chapter | verse | text |",
+                "| --- | --- | --- | --- | --- |",
+                "| Ps 23:2 | Ps | 23 | 2 | He [maketh] me to lie down in [green pastures]: he leadeth me beside the still waters. |",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_search_with_index() {
+        let searches = [
+            ("edge of the sword", "", "", "NT"),
+            ("ord is my shep", "", "", "Ps"),
+            ("", "seven", "", "NT"),
+            ("/prais(e|ed)/", "", "", "Ps 119"),
+            ("", "", "(grace OR mercy) AND NOT law", "Ga"),
+            ("", "", "faith NEAR/3 works", "Jas"),
+            ("sword", "+Lord", "\"the edge\" OR spear", "OT"),
+        ];
+        for (matching, word, query, include) in searches {
+            let params = SearchArgs {
+                matching: [matching]
+                    .iter()
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.to_string())
+                    .collect(),
+                word: [word]
+                    .iter()
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.to_string())
+                    .collect(),
+                query: Some(query.to_string()).filter(|s| !s.is_empty()),
+                include: vec![include.to_string()],
+                ..Default::default()
+            };
+            let without_index = search_internal(barfile(), &params).unwrap();
+            assert!(!without_index.is_empty());
+            let with_index = search_with(&params, Format::Text, Some(&INDEX)).unwrap();
+            assert_eq!(with_index, without_index);
+        }
+    }
+
+    #[test]
+    fn test_stem() {
+        let mut params = SearchArgs {
+            word: vec!["praise".to_string()],
+            ..count_args(&["Ps"])
+        };
+        assert_eq!(
+            count(&params, Format::Text),
+            vec!["Total: 130 (word count: 158)"]
+        );
+        // Stemming finds praised, praises, praiseth and praising as well
+        params.stem = true;
+        assert_eq!(
+            count(&params, Format::Text),
+            vec!["Total: 151 (word count: 187)"]
+        );
+        params.word = vec![];
+        params.query = Some("praising".to_string());
+        assert_eq!(count(&params, Format::Text), vec!["Total: 151"]);
+    }
+
+    #[test]
+    fn test_spelling() {
+        let mut params = SearchArgs {
+            word: vec!["show".to_string()],
+            ..count_args(&["Ps"])
+        };
+        assert_eq!(
+            count(&params, Format::Text),
+            vec!["Total: 0 (word count: 0)"]
+        );
+        // The KJV only ever spells it shew
+        params.spelling = true;
+        assert_eq!(
+            count(&params, Format::Text),
+            vec!["Total: 24 (word count: 26)"]
+        );
+        params.word = vec![];
+        params.query = Some("show".to_string());
+        assert_eq!(count(&params, Format::Text), vec!["Total: 24"]);
+        params.query = None;
+        params.matching = vec!["he spoke unto".to_string()];
+        params.include = vec!["Ge".to_string()];
+        assert_eq!(count(&params, Format::Text), vec!["Total: 6"]);
+    }
+
+    #[test]
+    fn test_unicode_words() {
+        let regex = word_regexp("señor").unwrap();
+        assert!(regex.is_match("Porque el SEÑOR es bueno"));
+        assert!(!regex.is_match("señores"));
+        let regex = word_regexp("Ἰησοῦς").unwrap();
+        assert!(regex.is_match("ὁ Ἰησοῦς εἶπεν"));
+        assert!(!regex.is_match("ὁ ἰησοῦς εἶπεν"));
+        assert_eq!(
+            words("בְּרֵאשִׁית בָּרָא").collect::<Vec<_>>(),
+            vec!["בְּרֵאשִׁית", "בָּרָא"]
+        );
+
+        let matching = WordMatching {
+            stem: false,
+            spellings: None,
+            ignore_diacritics: true,
+            normalize_phrases: false,
+        };
+        let regex = matching.pattern("senor").unwrap();
+        assert!(regex.is_match("Porque el SEÑOR es bueno"));
+        let regex = matching.pattern("über").unwrap();
+        assert!(regex.is_match("Uber alles") && regex.is_match("über"));
+        let regex = matching.pattern("Jesus").unwrap();
+        assert!(regex.is_match("Jesús") && !regex.is_match("jesús"));
+    }
+
+    #[test]
+    fn test_count_wildcard() {
+        let mut params = SearchArgs {
+            word: vec!["bless*".to_string()],
+            ..count_args(&["Ps"])
+        };
+        let output = count(&params, Format::Text);
+        assert_eq!(
+            output,
+            vec![
+                "Total: 93 (word count: 102)",
+                "  blessed: 47 (word count: 49)",
+                "  bless: 38 (word count: 44)",
+                "  blessing: 5 (word count: 5)",
+                "  blesseth: 2 (word count: 2)",
+                "  blessest: 1 (word count: 1)",
+                "  blessings: 1 (word count: 1)",
+            ]
+        );
+        // Including the capitalised Blessed when folding diacritics
+        params.ignore_diacritics = true;
+        assert_eq!(count(&params, Format::Text), output);
+        params.ignore_diacritics = false;
+        params.word = vec!["wom?n".to_string()];
+        assert_eq!(
+            count(&params, Format::Csv),
+            vec![
+                "reference,verses,words",
+                "Total,4,4",
+                "woman,3,3",
+                "women,1,1"
+            ]
+        );
+        params.word = vec![];
+        params.query = Some("wom?n OR bless*".to_string());
+        assert_eq!(count(&params, Format::Text), vec!["Total: 97"]);
+    }
+
+    #[test]
+    fn test_tally() {
+        let mut params = SearchArgs {
+            matching: vec![r"/\w+eth\b/".to_string()],
+            include: vec!["Ps 23".to_string()],
+            tally: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            search_with(&params, Format::Text, None).unwrap(),
+            vec!["leadeth: 2", "maketh: 1", "restoreth: 1", "runneth: 1"]
+        );
+        // Capture groups by name or number
+        params.matching = vec![r"/(\w+) of the (?<what>\w+)/".to_string()];
+        params.include = vec!["Ps".to_string()];
+        params.capture = Some("what".to_string());
+        let output = search_with(&params, Format::Csv, None).unwrap();
+        assert_eq!(
+            output[..4],
+            ["match,count", "Lord,107", "earth,42", "wicked,25"]
+        );
+        params.capture = Some("2".to_string());
+        assert_eq!(search_with(&params, Format::Csv, None).unwrap(), output);
+        params.capture = Some("3".to_string());
+        assert!(search_with(&params, Format::Text, None).is_err());
+        params.matching = vec!["shepherd".to_string()];
+        params.capture = None;
+        assert!(search_with(&params, Format::Text, None).is_err());
+    }
+
+    #[test]
+    fn test_regexp_ignoring_diacritics() {
+        // The KJV has no diacritics, so folding shouldn't change what
+        // regexps find
+        for regexp in [r"/\p{L}+/", "/[aeiou]n/", r"/\bJ[aeiou]c\w*b\b/"] {
+            let mut params = SearchArgs {
+                matching: vec![regexp.to_string()],
+                ..count_args(&["Ps"])
+            };
+            let output = count(&params, Format::Text);
+            params.ignore_diacritics = true;
+            assert_eq!(count(&params, Format::Text), output, "{}", regexp);
+        }
+    }
+
+    #[test]
+    fn test_normalize_phrases() {
+        let mut params = SearchArgs {
+            matching: vec!["in the beginning God".to_string()],
+            ..count_args(&[])
+        };
+        assert_eq!(count(&params, Format::Text), vec!["Total: 0"]);
+        params.normalize_phrases = true;
+        assert_eq!(count(&params, Format::Text), vec!["Total: 1"]);
+        params.matching = vec!["the LORD said  unto Moses".to_string()];
+        assert_eq!(count(&params, Format::Text), vec!["Total: 55"]);
+        // Punctuation and spaces at either end don't stop the words matching
+        // inside other words, with or without the index
+        params.matching = vec!["Lord,".to_string()];
+        assert_eq!(count(&params, Format::Text), vec!["Total: 6781"]);
+        params.matching = vec![" the".to_string()];
+        assert_eq!(count(&params, Format::Text), vec!["Total: 28001"]);
+    }
+
+    #[test]
+    fn test_parallel_search_matches_sequential() {
+        let searches = [
+            SearchArgs {
+                matching: vec!["/[Ss]hepherds?/".to_string()],
+                context: 1,
+                ..Default::default()
+            },
+            SearchArgs {
+                word: vec!["seven".to_string()],
+                count: true,
+                ..Default::default()
+            },
+        ];
+        for mut params in searches {
+            params.threads = Some(1);
+            let sequential = search_internal(barfile(), &params).unwrap();
+            params.threads = Some(4);
+            let parallel = search_internal(barfile(), &params).unwrap();
+            assert!(sequential.len() > 100);
+            assert_eq!(parallel, sequential);
+        }
+    }
+
+    #[test]
+    fn test_count_sevens_grouped() {
+        let mut params = SearchArgs {
+            word: vec!["seven".to_string()],
+            include: vec!["NT".to_string()],
+            count: true,
+            group_by: GroupBy::Book,
+            min_words: Some(10),
+            sort: CountSort::Count,
+            ..Default::default()
+        };
+        let output = search_internal(barfile(), &params).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "Rev: 31 (word count: 54)",
+                "Mt: 9 (word count: 10)",
+                "Total: 65 (word count: 91)"
+            ]
+        );
+        params.include = vec![];
+        params.group_by = GroupBy::Testament;
+        params.min_words = None;
+        params.sort = CountSort::Reference;
+        let output = search_internal(barfile(), &params).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "OT: 326 (word count: 372)",
+                "NT: 65 (word count: 91)",
+                "Total: 391 (word count: 463)"
+            ]
+        );
+        // Only words have a word count
+        params.word = vec![];
+        params.matching = vec!["seven".to_string()];
+        params.min_words = Some(10);
+        assert!(search_internal(barfile(), &params).is_err());
+    }
+
+    #[test]
+    fn test_count_matrix() {
+        let params = SearchArgs {
+            word: vec!["praise".to_string(), "bless".to_string()],
+            include: vec!["Ps 103-107".to_string()],
+            count: true,
+            ..Default::default()
+        };
+        let search = |format| search_with(&params, format, None).unwrap();
+        assert_eq!(
+            search(Format::Text),
+            vec![
+                "        verses  praise  bless",
+                "Ps 103       5       0      7",
+                "Ps 104       3       2      2",
+                "Ps 105       1       1      0",
+                "Ps 106       5       5      0",
+                "Ps 107       5       5      0",
+                "Total       19      13      9",
+            ]
+        );
+        assert_eq!(
+            search(Format::Csv),
+            vec![
+                "reference,verses,praise,bless",
+                "Ps 103,5,0,7",
+                "Ps 104,3,2,2",
+                "Ps 105,1,1,0",
+                "Ps 106,5,5,0",
+                "Ps 107,5,5,0",
+                "Total,19,13,9",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rank() {
+        let mut params = SearchArgs {
+            word: vec!["shepherd".to_string()],
+            include: vec!["Ps".to_string()],
+            rank: true,
+            top: 3,
+            ..Default::default()
+        };
+        let output = search_internal(barfile(), &params).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "8.584 Ps 23:1 The Lord is my shepherd; I shall not want.",
+                "6.348 Ps 80:1 Give ear, O Shepherd of Israel, thou that leadest Joseph like a flock; thou that dwellest between the cherubims, shine forth.",
+            ]
+        );
+        params.rank_by = RankBy::Chapter;
+        let output = search_with(&params, Format::Csv, None).unwrap();
+        assert_eq!(
+            output,
+            vec!["reference,score", "Ps 23,5.383", "Ps 80,3.831"]
+        );
+    }
+
+    #[test]
+    fn test_count_sevens_versions() {
+        let params = SearchArgs {
+            word: vec!["seven".to_string()],
+            include: vec!["Rev 15-22".to_string()],
+            count: true,
+            threshold: Some(7),
+            ..Default::default()
+        };
+        let bars = || {
+            vec![
+                ("KJV".to_string(), PathBuf::from(KJV), barfile()),
+                ("AV".to_string(), PathBuf::from(KJV), barfile()),
+            ]
+        };
+        let output = search_versions_internal(bars(), &params, Format::Text, false).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "== KJV ==",
+                "Rev 15: 4 (word count: 8)",
+                "Rev 17: 6 (word count: 8)",
+                "Total: 12 (word count: 20)",
+                "",
+                "== AV ==",
+                "Rev 15: 4 (word count: 8)",
+                "Rev 17: 6 (word count: 8)",
+                "Total: 12 (word count: 20)",
+                "",
+                "Total by version:",
+                "  KJV: 12 (word count: 20)",
+                "  AV: 12 (word count: 20)",
+            ]
+        );
+        let output = search_versions_internal(bars(), &params, Format::Csv, false).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "version,reference,verses,words",
+                "KJV,Rev 15,4,8",
+                "KJV,Rev 17,6,8",
+                "KJV,Total,12,20",
+                "AV,Rev 15,4,8",
+                "AV,Rev 17,6,8",
+                "AV,Total,12,20",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_count_sevens_csv() {
+        let params = SearchArgs {
+            word: vec!["seven".to_string()],
+            include: vec!["NT".to_string()],
+            count: true,
+            threshold: Some(7),
+            ..Default::default()
+        };
+        let output = search_with(&params, Format::Csv, None).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "reference,verses,words",
+                "Rev 1,6,12",
+                "Rev 15,4,8",
+                "Rev 17,6,8",
+                "Total,65,91"
+            ]
+        )
+    }
+
+    #[test]
+    fn test_context_across_chapters() {
+        let params = SearchArgs {
+            word: vec!["shepherd".to_string()],
+            include: vec!["Ps".to_string()],
+            before_context: 1,
+            after_context: 1,
+            ..Default::default()
+        };
+        let output = search_internal(barfile(), &params).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "  Ps 22:31 They shall come, and shall declare his righteousness unto a people that shall be born, that he hath done this.",
+                "> Ps 23:1 The Lord is my shepherd; I shall not want.",
+                "  Ps 23:2 He maketh me to lie down in green pastures: he leadeth me beside the still waters.",
+                "--",
+                "  Ps 79:13 So we thy people and sheep of thy pasture will give thee thanks for ever: we will shew forth thy praise to all generations.",
+                "> Ps 80:1 Give ear, O Shepherd of Israel, thou that leadest Joseph like a flock; thou that dwellest between the cherubims, shine forth.",
+                "  Ps 80:2 Before Ephraim and Benjamin and Manasseh stir up thy strength, and come and save us.",
+            ]
+        )
+    }
 }
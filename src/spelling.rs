@@ -0,0 +1,213 @@
+use regex::{Regex, RegexBuilder};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Archaic words and spellings, each group starting with the modern word
+const BUILTIN: [&[&str]; 36] = [
+    &["show", "shew"],
+    &["shows", "sheweth", "shewest", "showeth"],
+    &["showed", "shewed", "shewedst"],
+    &["showing", "shewing"],
+    &["shown", "shewn"],
+    &["has", "hath", "hast"],
+    &["had", "hadst"],
+    &["does", "doth", "dost", "doeth", "doest"],
+    &["did", "didst"],
+    &["says", "saith", "sayest"],
+    &["said", "saidst"],
+    &["spoke", "spake"],
+    &["you", "ye", "thee", "thou"],
+    &["your", "thy", "thine"],
+    &["yourself", "thyself"],
+    &["are", "art"],
+    &["were", "wast", "wert"],
+    &["will", "wilt"],
+    &["shall", "shalt"],
+    &["can", "canst"],
+    &["could", "couldest"],
+    &["would", "wouldest"],
+    &["should", "shouldest"],
+    &["since", "sith"],
+    &["always", "alway"],
+    &["ankle", "ancle"],
+    &["ankles", "ancles"],
+    &["steadfast", "stedfast"],
+    &["steadfastly", "stedfastly"],
+    &["music", "musick"],
+    &["public", "publick"],
+    &["cloak", "cloke"],
+    &["jail", "gaol"],
+    &["assuage", "asswage"],
+    &["mortar", "morter"],
+    &["soap", "sope"],
+];
+
+/// Groups of words that are different spellings of the same word, so that
+/// searching for one finds them all
+pub struct Spellings {
+    groups: Vec<Vec<String>>,
+    /// The group each word is in
+    lookup: HashMap<String, usize>,
+}
+
+impl Spellings {
+    pub fn builtin() -> Spellings {
+        let mut spellings = Spellings {
+            groups: Vec::new(),
+            lookup: HashMap::new(),
+        };
+        for group in BUILTIN {
+            spellings.add(group.iter().map(|word| word.to_string()).collect());
+        }
+        spellings
+    }
+
+    /// Add more groups from a file with a group of words on each line,
+    /// separated by spaces or commas and starting with the modern word.
+    /// Lines starting with # are ignored.
+    pub fn load(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) => {
+                return Err(format!("Cannot read {}: {}", path.to_string_lossy(), error).into());
+            }
+        };
+        for line in text
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+        {
+            let words: Vec<String> = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|word| !word.is_empty())
+                .map(|word| word.to_lowercase())
+                .collect();
+            if words.len() > 1 {
+                self.add(words);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a group, merging it with any groups its words are already in
+    fn add(&mut self, words: Vec<String>) {
+        let mut existing: Vec<usize> = words
+            .iter()
+            .filter_map(|word| self.lookup.get(word).copied())
+            .collect();
+        existing.sort();
+        existing.dedup();
+        let target = match existing.first() {
+            Some(i) => *i,
+            None => {
+                self.groups.push(Vec::new());
+                self.groups.len() - 1
+            }
+        };
+        let mut merged = std::mem::take(&mut self.groups[target]);
+        for i in existing.iter().skip(1) {
+            merged.extend(std::mem::take(&mut self.groups[*i]));
+        }
+        merged.extend(words);
+        let mut group: Vec<String> = Vec::new();
+        for word in merged {
+            if !group.contains(&word) {
+                self.lookup.insert(word.clone(), target);
+                group.push(word);
+            }
+        }
+        self.groups[target] = group;
+    }
+
+    /// The word and its other spellings. A capital first letter is kept.
+    pub fn variants(&self, word: &str) -> Vec<String> {
+        let group = match self.lookup.get(&word.to_lowercase()) {
+            Some(i) => &self.groups[*i],
+            None => return vec![word.to_string()],
+        };
+        let capitalised = word.starts_with(|c: char| c.is_uppercase());
+        let mut variants = vec![word.to_string()];
+        for variant in group {
+            let variant = match capitalised {
+                true => capitalise(variant),
+                false => variant.clone(),
+            };
+            if !variants.iter().any(|v| v.eq_ignore_ascii_case(&variant)) {
+                variants.push(variant);
+            }
+        }
+        variants
+    }
+
+    /// The modern spelling of the word, which all its spellings share
+    pub fn modern(&self, word: &str) -> String {
+        match self.lookup.get(word) {
+            Some(i) => self.groups[*i][0].clone(),
+            None => word.to_string(),
+        }
+    }
+
+    /// Regex matching the phrase with any of its words spelt differently, or
+    /// None if none of its words have other spellings
+    pub fn phrase_regexp(&self, phrase: &str) -> Option<Regex> {
+        let mut pattern = String::new();
+        let mut has_variants = false;
+        let mut rest = phrase;
+        while !rest.is_empty() {
+            let end = rest
+                .find(|c: char| c.is_alphanumeric() != rest.starts_with(char::is_alphanumeric))
+                .unwrap_or(rest.len());
+            let (piece, remainder) = rest.split_at(end);
+            let variants = self.variants(piece);
+            if variants.len() > 1 {
+                has_variants = true;
+                let escaped: Vec<String> = variants.iter().map(|v| regex::escape(v)).collect();
+                pattern.push_str(&format!("(?:{})", escaped.join("|")));
+            } else {
+                pattern.push_str(&regex::escape(piece));
+            }
+            rest = remainder;
+        }
+        if !has_variants {
+            return None;
+        }
+        RegexBuilder::new(&pattern).build().ok()
+    }
+}
+
+fn capitalise(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spellings() {
+        let mut spellings = Spellings::builtin();
+        assert_eq!(spellings.variants("show"), vec!["show", "shew"]);
+        assert_eq!(spellings.variants("Hath"), vec!["Hath", "Has", "Hast"]);
+        assert_eq!(spellings.variants("shepherd"), vec!["shepherd"]);
+        assert_eq!(spellings.modern("spake"), "spoke");
+        let regex = spellings.phrase_regexp("he spoke unto").unwrap();
+        assert!(regex.is_match("And he spake unto Moses"));
+        assert!(spellings.phrase_regexp("my shepherd").is_none());
+
+        let path = std::env::temp_dir().join(format!("bar-spellings-{}", std::process::id()));
+        fs::write(
+            &path,
+            "# Extra spellings\nshowbread, shewbread\nsavior saviour\n",
+        )
+        .unwrap();
+        spellings.load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(spellings.variants("savior"), vec!["savior", "saviour"]);
+        assert_eq!(spellings.modern("shewbread"), "showbread");
+    }
+}
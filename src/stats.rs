@@ -0,0 +1,228 @@
+use super::format::Table;
+use super::reference::testament;
+use super::search::Scope;
+use super::word::words;
+use super::{Breakdown, Format, StatsArgs, VerseRef};
+use bible_data::BOOK_ABBREVS;
+use biblearchive::BARFile;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{Read, Seek};
+
+/// How often each word occurs
+#[derive(Default)]
+struct Frequencies {
+    counts: HashMap<String, usize>,
+    total: usize,
+}
+
+impl Frequencies {
+    fn add(&mut self, text: &str) {
+        for word in words(text) {
+            *self.counts.entry(word).or_default() += 1;
+            self.total += 1;
+        }
+    }
+
+    fn unique(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Words that occur only once, in alphabetical order
+    fn hapax_legomena(&self) -> Vec<&str> {
+        let mut hapax: Vec<&str> = self
+            .counts
+            .iter()
+            .filter(|(_, count)| **count == 1)
+            .map(|(word, _)| &word[..])
+            .collect();
+        hapax.sort();
+        hapax
+    }
+
+    /// The most frequent words, with ties in alphabetical order
+    fn top(&self, n: usize) -> Vec<(&str, usize)> {
+        let mut top: Vec<(&str, usize)> = self
+            .counts
+            .iter()
+            .map(|(word, count)| (&word[..], *count))
+            .collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        top.truncate(n);
+        top
+    }
+}
+
+pub fn stats<T: Read + Seek>(bar: BARFile<T>, params: &StatsArgs, format: Format) -> i32 {
+    match stats_internal(bar, params, format) {
+        Err(error) => {
+            eprintln!("Error while calculating statistics");
+            eprintln!("{}", error);
+            1
+        }
+        Ok(_) => 0,
+    }
+}
+
+fn stats_internal<T: Read + Seek>(
+    bar: BARFile<T>,
+    params: &StatsArgs,
+    format: Format,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut output: Vec<String> = Vec::new();
+    let scope = Scope::parse(&params.include)?;
+    let mut all = Frequencies::default();
+    // Frequencies for each book or testament, in order
+    let mut groups: Vec<(String, Frequencies)> = Vec::new();
+    for book in bar.books_in_order() {
+        let b = book.book_number();
+        if !scope.includes_book(b) {
+            continue;
+        }
+        let group = match params.by {
+            Some(Breakdown::Book) => Some(BOOK_ABBREVS[b as usize - 1]),
+            Some(Breakdown::Testament) => Some(testament(b)),
+            None => None,
+        };
+        for chapter in book.chapters().flatten() {
+            let c = chapter.chapter_number();
+            if !scope.includes_chapter(b, c) {
+                continue;
+            }
+            for (v, verse) in chapter.enumerated_verses() {
+                if !scope.includes_verse(&VerseRef::new(b, c, v)) {
+                    continue;
+                }
+                all.add(&verse);
+                if let Some(group) = group {
+                    if groups.last().is_none_or(|(name, _)| name != group) {
+                        groups.push((group.to_string(), Frequencies::default()));
+                    }
+                    groups.last_mut().unwrap().1.add(&verse);
+                }
+            }
+        }
+    }
+
+    if !format.is_text() {
+        // The breakdown if there is one, otherwise the words listed
+        let mut table;
+        if params.by.is_some() {
+            table = Table::new(&["group", "words", "unique_words", "hapax_legomena"]);
+            for (name, frequencies) in groups.iter().chain([("Total".to_string(), all)].iter()) {
+                table.push(vec![
+                    name.as_str().into(),
+                    frequencies.total.into(),
+                    frequencies.unique().into(),
+                    frequencies.hapax_legomena().len().into(),
+                ]);
+            }
+        } else if params.hapax {
+            table = Table::new(&["word"]);
+            for word in all.hapax_legomena() {
+                table.push(vec![word.into()]);
+            }
+        } else {
+            table = Table::new(&["word", "count"]);
+            for (word, count) in all.top(params.top) {
+                table.push(vec![word.into(), count.into()]);
+            }
+        }
+        for line in table.render(format) {
+            oprintln!(output, "{}", line);
+        }
+        return Ok(output);
+    }
+
+    oprintln!(output, "Words: {}", all.total);
+    oprintln!(output, "Unique words: {}", all.unique());
+    let hapax = all.hapax_legomena();
+    oprintln!(output, "Hapax legomena: {}", hapax.len());
+    if params.hapax {
+        oprintln!(output, "Words occurring once:");
+        for word in hapax {
+            oprintln!(output, "  {}", word);
+        }
+    } else if params.top > 0 {
+        oprintln!(output, "Most frequent words:");
+        for (word, count) in all.top(params.top) {
+            oprintln!(output, "  {}: {}", word, count);
+        }
+    }
+    if let Some(by) = params.by {
+        let heading = match by {
+            Breakdown::Book => "By book:",
+            Breakdown::Testament => "By testament:",
+        };
+        oprintln!(output, "{}", heading);
+        for (name, frequencies) in groups.iter() {
+            oprintln!(
+                output,
+                "  {}: {} words, {} unique, {} hapax legomena",
+                name,
+                frequencies.total,
+                frequencies.unique(),
+                frequencies.hapax_legomena().len()
+            );
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    fn barfile() -> BARFile<File> {
+        BARFile::open("tests/data/KJV.ibar").unwrap()
+    }
+
+    #[test]
+    fn test_stats_by_testament() {
+        let params = StatsArgs {
+            include: vec!["Ps 117".to_string(), "Mt 1:1".to_string()],
+            top: 3,
+            hapax: false,
+            by: Some(Breakdown::Testament),
+        };
+        let output = stats_internal(barfile(), &params, Format::Text).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "Words: 49",
+                "Unique words: 29",
+                "Hapax legomena: 21",
+                "Most frequent words:",
+                "  the: 8",
+                "  of: 5",
+                "  lord: 3",
+                "By testament:",
+                "  OT: 33 words, 22 unique, 16 hapax legomena",
+                "  NT: 16 words, 9 unique, 6 hapax legomena",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stats_hapax_csv() {
+        let params = StatsArgs {
+            include: vec!["Mt 1:1".to_string()],
+            hapax: true,
+            ..Default::default()
+        };
+        let output = stats_internal(barfile(), &params, Format::Csv).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "word",
+                "abraham",
+                "book",
+                "christ",
+                "david",
+                "generation",
+                "jesus",
+            ]
+        );
+    }
+}
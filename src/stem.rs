@@ -0,0 +1,178 @@
+use super::word::{is_lower_case, is_word_char};
+use regex::{Regex, RegexBuilder};
+
+/// Endings removed to find the stem of a word, longest first, with the
+/// shortest stem each can leave. As well as the modern endings there are the
+/// Early Modern English ones for the second and third person singular e.g.
+/// lovest, loveth, lovedst.
+const SUFFIXES: [(&str, usize); 6] = [
+    ("edst", 2),
+    ("eth", 2),
+    ("est", 2),
+    ("ing", 3),
+    ("ed", 3),
+    ("e", 2),
+];
+
+/// Words that do not follow the rules, and the word they are a form of
+const IRREGULAR: [(&str, &str); 30] = [
+    ("am", "be"),
+    ("are", "be"),
+    ("art", "be"),
+    ("been", "be"),
+    ("being", "be"),
+    ("is", "be"),
+    ("was", "be"),
+    ("wast", "be"),
+    ("were", "be"),
+    ("wert", "be"),
+    ("did", "do"),
+    ("didst", "do"),
+    ("doing", "do"),
+    ("done", "do"),
+    ("dost", "do"),
+    ("doth", "do"),
+    ("going", "go"),
+    ("had", "have"),
+    ("hadst", "have"),
+    ("has", "have"),
+    ("hast", "have"),
+    ("hath", "have"),
+    ("said", "say"),
+    ("saidst", "say"),
+    ("saith", "say"),
+    ("seeing", "see"),
+    ("shalt", "shall"),
+    ("wilt", "will"),
+    ("canst", "can"),
+    ("couldest", "could"),
+];
+
+fn is_vowel(c: char) -> bool {
+    matches!(c, 'a' | 'e' | 'i' | 'o' | 'u')
+}
+
+/// The stem of a word, shared by all its inflections e.g. praise, praises,
+/// praised, praiseth and praising all have the stem "prais". Stems are not
+/// always words themselves.
+pub fn stem(word: &str) -> String {
+    let word = word.to_lowercase();
+    if let Some((_, base)) = IRREGULAR.iter().find(|(form, _)| *form == word) {
+        return stem(base);
+    }
+    let mut stem: &str = &word;
+    // Plurals, keeping words like bless, his and thus whole
+    if stem.len() > 2
+        && stem.ends_with('s')
+        && !["ss", "is", "us"].iter().any(|e| stem.ends_with(e))
+    {
+        stem = &stem[..stem.len() - 1];
+    }
+    for (suffix, shortest) in SUFFIXES {
+        if let Some(rest) = stem.strip_suffix(suffix)
+            && rest.chars().count() >= shortest
+        {
+            stem = rest;
+            // agreeing
+            if suffix == "ing" && stem.len() > 2 && stem.ends_with('e') {
+                stem = &stem[..stem.len() - 1];
+            }
+            break;
+        }
+    }
+    let mut stem: Vec<char> = stem.chars().collect();
+    // running, blessed
+    let n = stem.len();
+    if n > 2 && stem[n - 1] == stem[n - 2] && !is_vowel(stem[n - 1]) {
+        stem.pop();
+    }
+    // cry, cries, cried, crying
+    if stem.len() > 1 && stem.last() == Some(&'y') {
+        stem.pop();
+        stem.push('i');
+    }
+    stem.into_iter().collect()
+}
+
+/// Regex matching all the inflections of a word, or of each word in turn if
+/// there are several. As with whole words, it ignores case if the word is all
+/// lower case, otherwise only the case of the first letter matters.
+pub fn stem_regexp(word: &str) -> Option<Regex> {
+    let safe = word.replace(|c: char| !is_word_char(c) && c != ' ', "");
+    let ignore_case = is_lower_case(&safe);
+    let patterns: Vec<String> = safe.split_whitespace().map(inflections).collect();
+    if patterns.is_empty() {
+        return None;
+    }
+    RegexBuilder::new(&format!(r"\b{}\b", patterns.join(" ")))
+        .case_insensitive(ignore_case)
+        .build()
+        .ok()
+}
+
+/// Pattern for the words with the same stem as the word
+fn inflections(word: &str) -> String {
+    let stem = stem(word);
+    let last = stem.chars().last().unwrap_or_default();
+    let mut base = stem.clone();
+    if last == 'i' && base.len() > 1 {
+        base.pop();
+        base.push_str("(?:i|y)");
+    }
+    let mut forms = vec![format!(
+        "{}{}?(?:e?(?:edst|eth|est|ing|ed)|e)?s?",
+        base, last
+    )];
+    forms.extend(
+        IRREGULAR
+            .iter()
+            .filter(|(_, base)| self::stem(base) == stem)
+            .map(|(form, _)| form.to_string()),
+    );
+    if word.starts_with(|c: char| c.is_uppercase()) {
+        // Stems are lower case word characters, none of them special in a
+        // regexp, so the first letter can be capitalised on its own
+        for form in forms.iter_mut() {
+            let first = form.chars().next().unwrap_or_default();
+            let rest = &form[first.len_utf8()..];
+            *form = format!("{}(?i:{})", first.to_uppercase(), rest);
+        }
+    }
+    format!("(?:{})", forms.join("|"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stem() {
+        for words in [
+            &[
+                "praise", "praises", "praised", "praiseth", "praising", "praisest",
+            ][..],
+            &["love", "loved", "loveth", "lovest", "lovedst", "loving"],
+            &["bless", "blessed", "blesseth", "blessing", "blessings"],
+            &["cry", "cries", "cried", "crieth", "crying"],
+            &["run", "runneth", "running"],
+            &["say", "saith", "said", "sayest"],
+            &["see", "seeth", "seeing", "seest"],
+        ] {
+            let regex = stem_regexp(words[0]).unwrap();
+            for word in words {
+                assert_eq!(stem(word), stem(words[0]), "{}", word);
+                assert!(regex.is_match(word), "{}", word);
+            }
+        }
+        assert_ne!(stem("thing"), stem("the"));
+        assert_eq!(stem("priests"), stem("priest"));
+        let regex = stem_regexp("praise").unwrap();
+        assert!(!regex.is_match("appraise"));
+        assert!(!regex.is_match("prays"));
+        let regex = stem_regexp("Lord").unwrap();
+        assert!(regex.is_match("LORD") && regex.is_match("Lords"));
+        assert!(!regex.is_match("lord"));
+        let regex = stem_regexp("Ärger").unwrap();
+        assert!(regex.is_match("Ärger") && !regex.is_match("ärger"));
+    }
+}
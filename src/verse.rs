@@ -1,10 +1,18 @@
-use bible_data::parse_book_abbrev;
+use super::context::Context;
+use super::format::{Table, VERSE_COLUMNS, verse_row};
+use super::{Format, Layout, Passage, Reference, VerseRef};
 use biblearchive::BARFile;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::error::Error;
 use std::io::{Read, Seek};
 
-pub fn verse<T: Read + Seek>(bar: BARFile<T>, verses: &Vec<String>) -> i32 {
-    match verse_internal(bar, verses) {
+pub fn verse<T: Read + Seek>(
+    bar: BARFile<T>,
+    verses: &Vec<String>,
+    context: usize,
+    format: Format,
+) -> i32 {
+    match verse_internal(bar, verses, context, format) {
         Err(error) => {
             eprintln!("Error while fetching verses: {:?}", verses);
             eprintln!("{}", error);
@@ -14,47 +22,499 @@ pub fn verse<T: Read + Seek>(bar: BARFile<T>, verses: &Vec<String>) -> i32 {
     }
 }
 
+/// The verses of each chapter fetched so far, by book and chapter
+type Chapters = HashMap<(u8, u8), Vec<(u8, String)>>;
+
+/// The verses of a chapter, fetched from the archive the first time they
+/// are needed. A chapter missing from the archive has no verses.
+fn chapter_verses<'a, T: Read + Seek>(
+    bar: &BARFile<T>,
+    chapters: &'a mut Chapters,
+    b: u8,
+    c: u8,
+) -> &'a [(u8, String)] {
+    chapters.entry((b, c)).or_insert_with(|| {
+        bar.book(b)
+            .and_then(|book| book.chapter(c))
+            .map_or_else(Vec::new, |chapter| chapter.enumerated_verses().collect())
+    })
+}
+
+/// References output together as their context runs into each other, with
+/// the chapters they cover
+type ContextGroup = (Vec<Reference>, Vec<(u8, u8)>);
+
+/// The chapters covering the reference and the context verses either side
+/// of it, in order. The context stops at the start and end of the book.
+fn context_chapters<T: Read + Seek>(
+    bar: &BARFile<T>,
+    chapters: &mut Chapters,
+    reference: &Reference,
+    context: usize,
+) -> Vec<(u8, u8)> {
+    let (start, end) = (reference.start(), reference.end());
+    let mut needed = Vec::new();
+    let mut before = context.saturating_sub((start.verse as usize).saturating_sub(1));
+    let mut c = start.chapter;
+    while before > 0 && c > 1 {
+        c -= 1;
+        needed.push((start.book, c));
+        before = before.saturating_sub(chapter_verses(bar, chapters, start.book, c).len());
+    }
+    needed.reverse();
+    for b in start.book..=end.book {
+        let book = match bar.book(b) {
+            Some(book) => book,
+            None => continue,
+        };
+        let first = if b == start.book { start.chapter } else { 1 };
+        let last = if b == end.book { end.chapter } else { u8::MAX };
+        needed.extend((first..=last.min(book.number_of_chapters())).map(|c| (b, c)));
+    }
+    let last_chapter = bar
+        .book(end.book)
+        .map_or(0, |book| book.number_of_chapters());
+    let mut c = end.chapter.min(last_chapter);
+    let following = chapter_verses(bar, chapters, end.book, c)
+        .iter()
+        .filter(|(v, _)| *v > end.verse)
+        .count();
+    let mut after = context.saturating_sub(following);
+    while after > 0 && c < last_chapter {
+        c += 1;
+        needed.push((end.book, c));
+        after = after.saturating_sub(chapter_verses(bar, chapters, end.book, c).len());
+    }
+    needed
+}
+
+/// Whether two runs of chapters overlap or one follows straight on from the
+/// other, so that their context can run into each other
+fn are_touching(a: &[(u8, u8)], b: &[(u8, u8)]) -> bool {
+    match (a.first(), a.last(), b.first(), b.last()) {
+        (Some(&a_first), Some(&(ab, ac)), Some(&b_first), Some(&(bb, bc))) => {
+            b_first <= (ab, ac + 1) && a_first <= (bb, bc + 1)
+        }
+        _ => false,
+    }
+}
+
 fn verse_internal<T: Read + Seek>(
     bar: BARFile<T>,
     verses: &Vec<String>,
+    context: usize,
+    format: Format,
 ) -> Result<Vec<String>, Box<dyn Error>> {
     let mut output: Vec<String> = Vec::new();
+    let mut table = if context > 0 {
+        let mut columns = VERSE_COLUMNS.to_vec();
+        columns.push("hit");
+        Table::new(&columns)
+    } else {
+        Table::new(&VERSE_COLUMNS)
+    };
+    let mut verse_context = Context::new(context, context);
+    let mut chapters = Chapters::new();
     for verse in verses {
-        let book = parse_book_abbrev(verse);
-        if book.is_none() {
-            eprintln!("Invalid verse reference: {}", verse);
+        let passage = match Passage::parse(verse) {
+            Ok(passage) => passage,
+            Err(error) => {
+                eprintln!("{}", error);
+                continue;
+            }
+        };
+        if context > 0 {
+            // References whose context runs into each other are output
+            // together, otherwise they are output in the order given
+            let mut groups: Vec<ContextGroup> = Vec::new();
+            for reference in passage.references() {
+                let needed = context_chapters(&bar, &mut chapters, reference, context);
+                match groups.last_mut() {
+                    Some((references, group)) if are_touching(group, &needed) => {
+                        references.push(*reference);
+                        group.extend(needed);
+                        group.sort();
+                        group.dedup();
+                    }
+                    _ => groups.push((vec![*reference], needed)),
+                }
+            }
+            for (references, group) in groups {
+                verse_context.reset();
+                let mut previous: Option<(u8, u8)> = None;
+                for (b, c) in group {
+                    // Context does not run across books or missing chapters
+                    if previous.is_some_and(|(pb, pc)| (pb, pc + 1) != (b, c)) {
+                        verse_context.reset();
+                    }
+                    previous = Some((b, c));
+                    for (v, text) in chapter_verses(&bar, &mut chapters, b, c) {
+                        let verse_ref = VerseRef::new(b, c, *v);
+                        let is_hit = references.iter().any(|r| r.contains(&verse_ref));
+                        for line in verse_context.push(verse_ref, text.clone(), is_hit) {
+                            if format.is_text() {
+                                if line.is_new_group {
+                                    oprintln!(output, "--");
+                                }
+                                oprintln!(output, "{}", line);
+                            } else {
+                                let mut row = verse_row(&line.verse_ref, &line.text);
+                                row.push(line.is_hit.into());
+                                table.push(row);
+                            }
+                        }
+                    }
+                }
+            }
             continue;
         }
-        let book = book.unwrap();
-        let parts: Vec<&str> = verse.split(" ").collect();
-        if parts.len() > 3 {
-            eprintln!("Too many parts in verse reference: {}", verse);
-            continue;
+        for (verse_ref, text) in passage.verses_in(&bar)? {
+            if format.is_text() {
+                oprintln!(output, "{} {}", verse_ref, text);
+            } else {
+                table.push(verse_row(&verse_ref, &text));
+            }
         }
-        let refs: Vec<&str> = parts[1].split(":").collect();
-        if refs.len() != 2 {
-            eprintln!("Unexpected chapter:verse : {}", parts[1]);
-            continue;
+    }
+    for line in table.render(format) {
+        oprintln!(output, "{}", line);
+    }
+    Ok(output)
+}
+
+pub fn compare<T: Read + Seek>(
+    bars: Vec<(String, BARFile<T>)>,
+    verses: &[String],
+    layout: Layout,
+    width: usize,
+    format: Format,
+) -> i32 {
+    match compare_internal(bars, verses, layout, width, format) {
+        Err(error) => {
+            eprintln!("Error while comparing verses: {:?}", verses);
+            eprintln!("{}", error);
+            1
         }
-        let chapt = refs[0].parse();
-        if chapt.is_err() {
-            eprintln!("Non-numeric chapter: {}", refs[0]);
+        Ok(_) => 0,
+    }
+}
+
+/// Wrap text into lines no longer than width, breaking between words
+/// where possible
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        let line_len = line.chars().count();
+        if line_len > 0 && line_len + 1 + word.len() <= width {
+            line.push(' ');
+            line.extend(word.iter());
             continue;
         }
-        let chapt = chapt.unwrap();
-        let verse_number = refs[1].parse();
-        if verse_number.is_err() {
-            eprintln!("Non-numeric verse: {}", refs[1]);
-            continue;
+        if line_len > 0 {
+            lines.push(line);
+        }
+        // Words longer than a line have to be split
+        while word.len() > width {
+            lines.push(word.drain(..width).collect());
         }
-        let verse_number = verse_number.unwrap();
+        line = word.into_iter().collect();
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Lay out the cells side by side in columns of the given width
+fn columns_row(cells: &[&str], width: usize) -> String {
+    let row: Vec<String> = cells
+        .iter()
+        .map(|cell| format!("{:width$}", cell, width = width))
+        .collect();
+    row.join(" | ").trim_end().to_string()
+}
 
-        if let Some(book) = bar.book((book + 1) as u8) {
-            if let Some(chapt) = book.chapter(chapt) {
-                let verse_text = chapt.verse_text(verse_number)?;
-                oprintln!(output, "{} {}", verse, verse_text);
+/// The verses of a reference fetched from a version
+type Fetched = Result<Vec<(VerseRef, String)>, Box<dyn Error>>;
+
+/// The verses of a passage in each version, lined up by reference
+struct LinedUp {
+    order: Vec<VerseRef>,
+    seen: HashSet<VerseRef>,
+    texts: Vec<HashMap<VerseRef, String>>,
+}
+
+impl LinedUp {
+    fn new(versions: usize) -> LinedUp {
+        LinedUp {
+            order: Vec::new(),
+            seen: HashSet::new(),
+            texts: vec![HashMap::new(); versions],
+        }
+    }
+
+    /// Add the verses of a reference fetched from each version. A verse may
+    /// be missing from some of the versions, so it is only an error for the
+    /// reference to be missing from all of them.
+    fn add(&mut self, fetched: Vec<Fetched>) -> Result<(), Box<dyn Error>> {
+        let mut found: BTreeSet<VerseRef> = BTreeSet::new();
+        let mut error = None;
+        for (texts, verses) in self.texts.iter_mut().zip(fetched) {
+            match verses {
+                Ok(verses) => {
+                    for (verse_ref, text) in verses {
+                        found.insert(verse_ref);
+                        texts.insert(verse_ref, text);
+                    }
+                }
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
             }
         }
+        if let Some(error) = error.filter(|_| found.is_empty()) {
+            return Err(error);
+        }
+        // In order, whichever versions the verses came from
+        for verse_ref in found {
+            if self.seen.insert(verse_ref) {
+                self.order.push(verse_ref);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn compare_internal<T: Read + Seek>(
+    bars: Vec<(String, BARFile<T>)>,
+    verses: &[String],
+    layout: Layout,
+    width: usize,
+    format: Format,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut output: Vec<String> = Vec::new();
+    let mut table = Table::new(&["reference", "version", "text"]);
+    let separators = 3 * bars.len().saturating_sub(1);
+    let column_width = width.saturating_sub(separators) / bars.len().max(1);
+    if format.is_text() && layout == Layout::Columns {
+        let names: Vec<&str> = bars.iter().map(|(name, _)| &name[..]).collect();
+        oprintln!(output, "{}", columns_row(&names, column_width));
+    }
+    for verse in verses {
+        let passage = match Passage::parse(verse) {
+            Ok(passage) => passage,
+            Err(error) => {
+                eprintln!("{}", error);
+                continue;
+            }
+        };
+        // Fetch the passage from every version and line the verses up by
+        // reference, as a verse may be missing from some of the versions
+        let mut lined_up = LinedUp::new(bars.len());
+        for reference in passage.references() {
+            lined_up.add(
+                bars.iter()
+                    .map(|(_, bar)| reference.verses_in(bar))
+                    .collect(),
+            )?;
+        }
+        let LinedUp { order, texts, .. } = lined_up;
+        for verse_ref in order.iter() {
+            if !format.is_text() {
+                for ((name, _), version_texts) in bars.iter().zip(texts.iter()) {
+                    if let Some(text) = version_texts.get(verse_ref) {
+                        table.push(vec![
+                            verse_ref.to_string().into(),
+                            name.as_str().into(),
+                            text.as_str().into(),
+                        ]);
+                    }
+                }
+                continue;
+            }
+            oprintln!(output, "{}", verse_ref);
+            match layout {
+                Layout::Interleaved => {
+                    for ((name, _), version_texts) in bars.iter().zip(texts.iter()) {
+                        if let Some(text) = version_texts.get(verse_ref) {
+                            oprintln!(output, "  {}: {}", name, text);
+                        }
+                    }
+                }
+                Layout::Columns => {
+                    let wrapped: Vec<Vec<String>> = texts
+                        .iter()
+                        .map(|version_texts| match version_texts.get(verse_ref) {
+                            Some(text) => wrap(text, column_width),
+                            None => Vec::new(),
+                        })
+                        .collect();
+                    let rows = wrapped.iter().map(|lines| lines.len()).max().unwrap_or(0);
+                    for i in 0..rows {
+                        let cells: Vec<&str> = wrapped
+                            .iter()
+                            .map(|lines| lines.get(i).map_or("", |line| &line[..]))
+                            .collect();
+                        oprintln!(output, "{}", columns_row(&cells, column_width));
+                    }
+                }
+            }
+        }
+    }
+    for line in table.render(format) {
+        oprintln!(output, "{}", line);
     }
     Ok(output)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    fn barfile() -> BARFile<File> {
+        BARFile::open("tests/data/KJV.ibar").unwrap()
+    }
+
+    #[test]
+    fn test_cross_chapter_passage() {
+        let verses = vec!["Ps 22:31-23:2".to_string()];
+        let output = verse_internal(barfile(), &verses, 0, Format::Text).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "Ps 22:31 They shall come, and shall declare his righteousness unto a people that shall be born, that he hath done this.",
+                "Ps 23:1 The Lord is my shepherd; I shall not want.",
+                "Ps 23:2 He maketh me to lie down in green pastures: he leadeth me beside the still waters.",
+            ]
+        )
+    }
+
+    #[test]
+    fn test_whole_chapter_and_book() {
+        let verses = vec!["Ps 117".to_string(), "Jude".to_string()];
+        let output = verse_internal(barfile(), &verses, 0, Format::Text).unwrap();
+        assert_eq!(output.len(), 2 + 25);
+        assert_eq!(
+            output[1],
+            "Ps 117:2 For his merciful kindness is great toward us: and the truth of the Lord endureth for ever. Praise ye the Lord."
+        );
+        assert_eq!(
+            output[26],
+            "Jude 1:25 To the only wise God our Saviour, be glory and majesty, dominion and power, both now and ever. Amen."
+        );
+    }
+
+    #[test]
+    fn test_verse_context() {
+        let verses = vec!["Ps 22:31, 23:2".to_string()];
+        let output = verse_internal(barfile(), &verses, 1, Format::Text).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "  Ps 22:30 A seed shall serve him; it shall be accounted to the Lord for a generation.",
+                "> Ps 22:31 They shall come, and shall declare his righteousness unto a people that shall be born, that he hath done this.",
+                "  Ps 23:1 The Lord is my shepherd; I shall not want.",
+                "> Ps 23:2 He maketh me to lie down in green pastures: he leadeth me beside the still waters.",
+                "  Ps 23:3 He restoreth my soul: he leadeth me in the paths of righteousness for his name's sake.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verse_context_in_passage_order() {
+        let verses = vec!["Ps 23:1, Ge 1:1".to_string()];
+        let output = verse_internal(barfile(), &verses, 1, Format::Text).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "  Ps 22:31 They shall come, and shall declare his righteousness unto a people that shall be born, that he hath done this.",
+                "> Ps 23:1 The Lord is my shepherd; I shall not want.",
+                "  Ps 23:2 He maketh me to lie down in green pastures: he leadeth me beside the still waters.",
+                "--",
+                "> Ge 1:1 In the beginning God created the heaven and the earth.",
+                "  Ge 1:2 And the earth was without form, and void; and darkness was upon the face of the deep. And the Spirit of God moved upon the face of the waters.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(
+            wrap("The Lord is my shepherd; I shall not want.", 12),
+            vec!["The Lord is", "my shepherd;", "I shall not", "want."]
+        );
+        assert_eq!(
+            wrap("Maher-shalal-hash-baz", 8),
+            vec!["Maher-sh", "alal-has", "h-baz"]
+        );
+    }
+
+    #[test]
+    fn test_compare_columns() {
+        let bars = vec![
+            ("KJV".to_string(), barfile()),
+            ("AV".to_string(), barfile()),
+        ];
+        let verses = vec!["Ps 23:1".to_string()];
+        let output = compare_internal(bars, &verses, Layout::Columns, 43, Format::Text).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "KJV                  | AV",
+                "Ps 23:1",
+                "The Lord is my       | The Lord is my",
+                "shepherd; I shall    | shepherd; I shall",
+                "not want.            | not want.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_line_up_missing_verse() {
+        let reference = Reference::parse("Mt 18:10-12").unwrap();
+        let kjv = reference.verses_in(&barfile());
+        // As in versions that leave out Mt 18:11
+        let missing: Vec<(VerseRef, String)> = reference
+            .verses_in(&barfile())
+            .unwrap()
+            .into_iter()
+            .filter(|(verse_ref, _)| verse_ref.verse != 11)
+            .collect();
+        let mut lined_up = LinedUp::new(3);
+        lined_up
+            .add(vec![kjv, Ok(missing), Err("Not in the archive".into())])
+            .unwrap();
+        let verse = |v| VerseRef::new(40, 18, v);
+        assert_eq!(lined_up.order, vec![verse(10), verse(11), verse(12)]);
+        assert!(lined_up.texts[0].contains_key(&verse(11)));
+        assert!(!lined_up.texts[1].contains_key(&verse(11)));
+        assert!(lined_up.texts[2].is_empty());
+
+        let mut lined_up = LinedUp::new(2);
+        let missing = || Reference::parse("Ps 151:1").unwrap().verses_in(&barfile());
+        assert!(lined_up.add(vec![missing(), missing()]).is_err());
+    }
+
+    #[test]
+    fn test_compare_interleaved() {
+        let bars = vec![
+            ("KJV".to_string(), barfile()),
+            ("AV".to_string(), barfile()),
+        ];
+        let verses = vec!["Ps 23:1".to_string()];
+        let output =
+            compare_internal(bars, &verses, Layout::Interleaved, 120, Format::Text).unwrap();
+        assert_eq!(
+            output,
+            vec![
+                "Ps 23:1",
+                "  KJV: The Lord is my shepherd; I shall not want.",
+                "  AV: The Lord is my shepherd; I shall not want.",
+            ]
+        );
+    }
+}
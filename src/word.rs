@@ -0,0 +1,47 @@
+use regex::{Regex, RegexBuilder};
+use unicode_normalization::char::is_combining_mark;
+
+/// Whether the character is part of a word. As with the word boundaries of
+/// regexes, combining marks such as accents and Hebrew vowel points are.
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || is_combining_mark(c)
+}
+
+/// Split verse text into lower case words, at the same places as the word
+/// boundaries used to match words
+pub fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !is_word_char(c))
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+}
+
+/// Whether the word has shell-style wildcards, * for any letters and ? for
+/// any one letter
+pub fn is_wildcard(word: &str) -> bool {
+    word.contains(['*', '?'])
+}
+
+/// Whether the letters of the word are all lower case, in which case it is
+/// matched in any case. Wildcards, spaces and diacritics are not letters.
+pub fn is_lower_case(word: &str) -> bool {
+    word.chars()
+        .filter(|c| c.is_alphabetic())
+        .all(|c| c.is_lowercase())
+}
+
+pub fn word_regexp(word: &str) -> Option<Regex> {
+    // Get rid of any non alpha-numerics other than wildcards
+    let safe = word.replace(|c: char| !is_word_char(c) && !" *?".contains(c), "");
+    if !safe.contains(is_word_char) {
+        return None;
+    }
+    // If word is all lower-case assume we want case-insensitive search
+    let ignore_case = is_lower_case(&safe);
+    // Convert to a regex that will match on word boundaries
+    let safe = safe.replace('*', r"\w*").replace('?', r"\w");
+    let regex = format!(r"\b{}\b", safe);
+    RegexBuilder::new(&regex)
+        .case_insensitive(ignore_case)
+        .build()
+        .ok()
+}